@@ -0,0 +1,179 @@
+use crate::skeleton::*;
+use anyhow::{Result, bail};
+use nalgebra_glm::*;
+use std::io::Write;
+
+pub struct Options {
+    /// Axes (0 = X, 1 = Y, 2 = Z) of the rotation channels, in the order they are listed and
+    /// applied.
+    pub rotation_order: [usize; 3],
+    /// Multiplier from model units to the units of the BVH file.
+    pub scale: f32,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            rotation_order: [2, 0, 1],
+            scale: 1.0,
+        }
+    }
+}
+
+/// Parses a rotation order such as `ZXY`.
+pub fn parse_rotation_order(order: &str) -> Result<[usize; 3]> {
+    let axes: Vec<usize> = order
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            'X' => Ok(0),
+            'Y' => Ok(1),
+            'Z' => Ok(2),
+            _ => bail!("Invalid rotation order {order}"),
+        })
+        .collect::<Result<_>>()?;
+    let [a, b, c] = axes[..] else {
+        bail!("Invalid rotation order {order}")
+    };
+    if a == b || b == c || a == c {
+        bail!("Invalid rotation order {order}")
+    }
+    Ok([a, b, c])
+}
+
+const AXIS_NAMES: [&str; 3] = ["X", "Y", "Z"];
+
+/// Decomposes `m` into angles (in radians) such that `m = R[i](a) * R[j](b) * R[k](c)` for
+/// `order = [i, j, k]`.
+pub fn euler_angles(m: &Mat3, order: [usize; 3]) -> [f32; 3] {
+    let [i, j, k] = order;
+    let sign = if (j + 3 - i) % 3 == 1 { 1.0 } else { -1.0 };
+    let b = (sign * m[(i, k)]).clamp(-1.0, 1.0).asin();
+    if b.cos() > 1e-6 {
+        let a = (-sign * m[(j, k)]).atan2(m[(k, k)]);
+        let c = (-sign * m[(i, j)]).atan2(m[(i, i)]);
+        [a, b, c]
+    } else {
+        // Gimbal lock. Only a + c or a - c is determined, so put it all in a.
+        let a = (sign * m[(k, j)]).atan2(m[(j, j)]);
+        [a, b, 0.0]
+    }
+}
+
+struct BvhWriter<'a, W: Write> {
+    writer: &'a mut W,
+    bones: &'a [Bone],
+    options: &'a Options,
+    /// Channel values of the single frame, in the order the channels are declared.
+    frame: Vec<f32>,
+}
+
+impl<W: Write> BvhWriter<'_, W> {
+    fn rotation_channels(&self) -> String {
+        self.options
+            .rotation_order
+            .map(|axis| format!("{}rotation", AXIS_NAMES[axis]))
+            .join(" ")
+    }
+
+    fn write_joint(&mut self, index: u16, depth: usize, root: bool) -> Result<()> {
+        let bone = &self.bones[index as usize];
+        let indent = "  ".repeat(depth);
+        let name: String = bone
+            .name
+            .chars()
+            .map(|c| if c.is_whitespace() { '_' } else { c })
+            .collect();
+        let offset = bone.position.xyz() * self.options.scale;
+
+        writeln!(
+            self.writer,
+            "{indent}{} {name}",
+            if root { "ROOT" } else { "JOINT" }
+        )?;
+        writeln!(self.writer, "{indent}{{")?;
+        writeln!(
+            self.writer,
+            "{indent}  OFFSET {} {} {}",
+            offset.x, offset.y, offset.z
+        )?;
+        if root {
+            writeln!(
+                self.writer,
+                "{indent}  CHANNELS 6 Xposition Yposition Zposition {}",
+                self.rotation_channels()
+            )?;
+            self.frame.extend_from_slice(offset.as_slice());
+        } else {
+            writeln!(
+                self.writer,
+                "{indent}  CHANNELS 3 {}",
+                self.rotation_channels()
+            )?;
+        }
+
+        // BVH has no rest rotations, so they go into the frame instead.
+        let rotation = quat_to_mat3(&Quat::from_vector(bone.rotation));
+        let angles = euler_angles(&rotation, self.options.rotation_order);
+        self.frame.extend(angles.map(|a| a.to_degrees()));
+
+        if bone.children.is_empty() {
+            writeln!(self.writer, "{indent}  End Site")?;
+            writeln!(self.writer, "{indent}  {{")?;
+            writeln!(self.writer, "{indent}    OFFSET 0 0 0")?;
+            writeln!(self.writer, "{indent}  }}")?;
+        }
+        for &child in &bone.children {
+            self.write_joint(child, depth + 1, false)?;
+        }
+        writeln!(self.writer, "{indent}}}")?;
+        Ok(())
+    }
+}
+
+/// Writes the rest pose of a skeleton as a single-frame BVH file.
+///
+/// BVH can only express a single root and no scale, so a skeleton with multiple roots gets an
+/// extra `boneroot` joint at the origin, and bone scale is dropped.
+pub fn export(skeleton: &Skeleton, options: &Options, writer: &mut impl Write) -> Result<()> {
+    let bones = &skeleton.bones;
+    let root_bones = &skeleton.root_bones[..];
+    if root_bones.is_empty() {
+        bail!("No bones to export");
+    }
+    if bones.iter().any(|b| b.scale.xyz() != vec3(1.0, 1.0, 1.0)) {
+        eprintln!("Bone scale isn't supported by BVH and is dropped");
+    }
+
+    let mut bvh = BvhWriter {
+        writer,
+        bones,
+        options,
+        frame: vec![],
+    };
+    writeln!(bvh.writer, "HIERARCHY")?;
+    if let [root] = root_bones {
+        bvh.write_joint(*root, 0, true)?;
+    } else {
+        writeln!(bvh.writer, "ROOT boneroot")?;
+        writeln!(bvh.writer, "{{")?;
+        writeln!(bvh.writer, "  OFFSET 0 0 0")?;
+        writeln!(
+            bvh.writer,
+            "  CHANNELS 6 Xposition Yposition Zposition {}",
+            bvh.rotation_channels()
+        )?;
+        bvh.frame.extend_from_slice(&[0.0; 6]);
+        for &root in root_bones {
+            bvh.write_joint(root, 1, false)?;
+        }
+        writeln!(bvh.writer, "}}")?;
+    }
+
+    writeln!(bvh.writer, "MOTION")?;
+    writeln!(bvh.writer, "Frames: 1")?;
+    writeln!(bvh.writer, "Frame Time: {}", 1.0 / 30.0)?;
+    // Adding zero turns -0 into 0
+    let frame: Vec<String> = bvh.frame.iter().map(|v| (v + 0.0).to_string()).collect();
+    writeln!(bvh.writer, "{}", frame.join(" "))?;
+    Ok(())
+}
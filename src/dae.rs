@@ -0,0 +1,1075 @@
+use crate::bvh;
+use crate::collada::{self, *};
+use crate::model::{self, *};
+use crate::skeleton::*;
+use anyhow::*;
+use nalgebra_glm::*;
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How bones and meshes are identified in the exported document.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Naming {
+    /// `bone{index}` and `mesh{index}`, with the real name only as a display name.
+    #[default]
+    Index,
+    /// The names from the string table, so that rigs of different models line up.
+    Name,
+}
+
+/// Where the local transforms of the joints come from.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum BindPose {
+    /// The rest pose stored in the bone tree.
+    #[default]
+    Tree,
+    /// The inverse of the inverse bind matrices, so that skinned meshes sit exactly on the rig
+    /// even where the tree disagrees with them.
+    InverseBind,
+}
+
+/// How joint transforms are written.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Transform {
+    /// A single baked `<matrix>`.
+    #[default]
+    Matrix,
+    /// Separate `<translate>`, `<rotate>` and `<scale>` elements, which are easier to edit and
+    /// animate.
+    Trs,
+}
+
+/// The axis pointing up in the exported document.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Up {
+    /// The axis the game uses.
+    #[default]
+    Y,
+    Z,
+}
+
+/// Whether the exported coordinates are right or left handed. The game's are right handed.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Handedness {
+    #[default]
+    Right,
+    Left,
+}
+
+/// A unit of length.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    Metre,
+    Centimetre,
+}
+
+impl LengthUnit {
+    pub fn meters(self) -> f32 {
+        match self {
+            LengthUnit::Metre => 1.0,
+            LengthUnit::Centimetre => 0.01,
+        }
+    }
+}
+
+/// Where the `<created>` and `<modified>` dates come from.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum Timestamp {
+    /// A fixed date, so that the output only depends on the input and the options.
+    #[default]
+    Fixed,
+    /// The time of the export.
+    Now,
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub naming: Naming,
+    pub bind_pose: BindPose,
+    pub transform: Transform,
+    /// Writes the index buffers as stored, as `<tristrips>`, instead of unfolding them into
+    /// `<triangles>`.
+    pub strips: bool,
+    /// Written as `<up_axis>` when set.
+    pub up_axis: Option<Up>,
+    pub handedness: Handedness,
+    /// The length of a model unit. The game doesn't record it, so `<unit>` is only written when
+    /// this is given.
+    pub source_unit: Option<LengthUnit>,
+    /// The unit to rescale to. Needs `source_unit`.
+    pub unit: Option<LengthUnit>,
+    pub timestamp: Timestamp,
+    /// Name of the exported file, for `<source_data>`.
+    pub source_file: Option<String>,
+    pub title: Option<String>,
+    pub keywords: Vec<String>,
+    pub version: Version,
+    pub format: Format,
+}
+
+impl Options {
+    /// Rotation or mirroring from the game's axes to the exported ones.
+    fn axes(&self) -> Mat3 {
+        let rows = match (self.up_axis.unwrap_or_default(), self.handedness) {
+            (Up::Y, Handedness::Right) => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            (Up::Y, Handedness::Left) => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]],
+            (Up::Z, Handedness::Right) => [[1.0, 0.0, 0.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]],
+            (Up::Z, Handedness::Left) => [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]],
+        };
+        Mat3::from_fn(|r, c| rows[r][c])
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.unit.is_some() && self.source_unit.is_none() {
+            bail!("Rescaling needs the unit of the model, see --source-unit")
+        }
+        Ok(())
+    }
+
+    /// Multiplier from model units to exported units.
+    fn scale(&self) -> f32 {
+        match (self.source_unit, self.unit) {
+            (Some(source), Some(unit)) => source.meters() / unit.meters(),
+            _ => 1.0,
+        }
+    }
+
+    /// Whether the axes or units change at all.
+    fn converts(&self) -> bool {
+        self.axes() != Mat3::identity() || self.scale() != 1.0
+    }
+
+    /// Whether the conversion mirrors, which flips triangle winding and tangent handedness.
+    fn mirrors(&self) -> bool {
+        self.handedness == Handedness::Left
+    }
+
+    /// Expresses a transform between game spaces in exported coordinates.
+    fn convert(&self, m: &Mat4x4) -> Mat4x4 {
+        if !self.converts() {
+            return *m;
+        }
+        let axes = self.axes();
+        let s = self.scale();
+        let to = scaling(&vec3(s, s, s)) * mat3_to_mat4(&axes);
+        let from = mat3_to_mat4(&axes.transpose()) * scaling(&vec3(1.0 / s, 1.0 / s, 1.0 / s));
+        to * m * from
+    }
+}
+
+/// Turns `name` into something usable as both an id (an NCName) and a sid.
+fn sanitize(name: &str) -> String {
+    let mut result: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if !result.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        result.insert(0, '_');
+    }
+    result
+}
+
+/// Element ids of bones and meshes, shared by everything that references them.
+struct Ids {
+    bones: Vec<String>,
+    meshs: Vec<String>,
+    controllers: Vec<String>,
+    materials: Vec<String>,
+}
+
+impl Ids {
+    fn new(
+        skeleton: &Skeleton,
+        meshs: &[Mesh],
+        materials: &[model::Material],
+        naming: Naming,
+    ) -> Ids {
+        match naming {
+            Naming::Index => Ids {
+                bones: (0..skeleton.bones.len())
+                    .map(|i| format!("bone{i}"))
+                    .collect(),
+                meshs: (0..meshs.len()).map(|i| format!("mesh{i}")).collect(),
+                controllers: (0..meshs.len()).map(|i| format!("controller{i}")).collect(),
+                materials: (0..materials.len())
+                    .map(|i| format!("material{i}"))
+                    .collect(),
+            },
+            Naming::Name => {
+                let mut used: HashSet<String> = ["boneroot", "scene"].map(|s| s.to_owned()).into();
+                // Reserves `name` plus the ids derived from it by appending `suffixes`,
+                // numbering it until none of them collide.
+                let mut unique = |name: &str, suffixes: &[&str]| -> String {
+                    let base = sanitize(name);
+                    let mut candidate = base.clone();
+                    let mut n = 0;
+                    while suffixes
+                        .iter()
+                        .any(|suffix| used.contains(&format!("{candidate}{suffix}")))
+                    {
+                        n += 1;
+                        candidate = format!("{base}_{n}");
+                    }
+                    for suffix in suffixes {
+                        used.insert(format!("{candidate}{suffix}"));
+                    }
+                    candidate
+                };
+                let bones = skeleton
+                    .bones
+                    .iter()
+                    .map(|b| unique(&b.name, &[""]))
+                    .collect();
+                // Every id `export` derives from a mesh id, including those of its sources and of
+                // its skin controller's sources.
+                let mesh_suffixes = |mesh: &Mesh| -> Vec<String> {
+                    let mut suffixes: Vec<String> = ["", "-node", "-vertices", "-skin"]
+                        .map(|s| s.to_owned())
+                        .into();
+                    for source in ["joint", "inv", "weight"] {
+                        suffixes.push(format!("-skin-{source}"));
+                        suffixes.push(format!("-skin-{source}-array"));
+                    }
+                    for i in 0..mesh.attrs.len() {
+                        suffixes.push(format!("-attr{i}"));
+                        suffixes.push(format!("-attr{i}-array"));
+                    }
+                    suffixes
+                };
+                let meshs: Vec<String> = meshs
+                    .iter()
+                    .map(|m| {
+                        let suffixes = mesh_suffixes(m);
+                        let suffixes: Vec<&str> = suffixes.iter().map(String::as_str).collect();
+                        unique(&m.name, &suffixes)
+                    })
+                    .collect();
+                let controllers = meshs.iter().map(|m| format!("{m}-skin")).collect();
+                let materials = materials
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| match &m.name {
+                        Some(name) => unique(name, &["", "-effect"]),
+                        None => unique(&format!("material{i}"), &["", "-effect"]),
+                    })
+                    .collect();
+                Ids {
+                    bones,
+                    meshs,
+                    controllers,
+                    materials,
+                }
+            }
+        }
+    }
+}
+
+/// How a mesh is attached to the scene.
+#[derive(Clone, Copy)]
+enum Binding {
+    /// No bones at all. Instanced as a plain geometry at the scene root.
+    Static,
+    /// Follows a single bone rigidly. Instanced as a plain geometry under that bone.
+    Rigid(u32),
+    /// Deformed by a skin controller.
+    Skinned,
+}
+
+/// The local transform of every joint, according to `bind_pose`.
+///
+/// With [`BindPose::InverseBind`], a joint's local matrix is its parent's inverse bind matrix
+/// times the inverse of its own. Bones whose inverse bind matrix can't be inverted keep their
+/// tree transform.
+fn joint_matrices(skeleton: &Skeleton, inv_matrixs: &[Mat4x4], bind_pose: BindPose) -> Vec<Mat4x4> {
+    let bones = &skeleton.bones;
+    let mut locals: Vec<Mat4x4> = bones.iter().map(Bone::local_matrix).collect();
+    if bind_pose == BindPose::Tree {
+        return locals;
+    }
+    for (i, bone) in bones.iter().enumerate() {
+        let Some(bind) = inv_matrixs.get(i).and_then(|m| m.try_inverse()) else {
+            eprintln!("bone{i} has no invertible inverse bind matrix, using the tree");
+            continue;
+        };
+        locals[i] = match inv_matrixs.get(bone.parent as usize) {
+            Some(parent_inv) if bone.parent != 0x7FFF => parent_inv * bind,
+            _ => bind,
+        };
+    }
+    locals
+}
+
+fn trs(translate: Vec3, rotation: &Mat3, scale: Vec3) -> Trs {
+    let [z, y, x] = bvh::euler_angles(rotation, [2, 1, 0]);
+    Trs {
+        translate,
+        // Adding zero turns -0 into 0
+        rotate: vec3(x, y, z).map(|a| a.to_degrees() + 0.0),
+        scale,
+    }
+}
+
+/// Splits a matrix without shear into its components.
+fn decompose(m: &Mat4x4) -> Trs {
+    let mut scale = vec3(
+        m.column(0).xyz().norm(),
+        m.column(1).xyz().norm(),
+        m.column(2).xyz().norm(),
+    );
+    if determinant(m) < 0.0 {
+        scale.x = -scale.x;
+    }
+    let inv_scale = scale.map(|s| if s != 0.0 { 1.0 / s } else { 0.0 });
+    let rotation = mat4_to_mat3(m) * Mat3::from_diagonal(&inv_scale);
+    trs(m.column(3).xyz(), &rotation, scale)
+}
+
+/// The transform of a joint's node, as a matrix or as components depending on the options.
+fn joint_transform(
+    bone: &Bone,
+    local: &Mat4x4,
+    options: &Options,
+) -> (Option<Mat4x4>, Option<Trs>) {
+    match options.transform {
+        Transform::Matrix => (Some(*local), None),
+        // The tree already has the components, only matrices from elsewhere need taking apart.
+        Transform::Trs if options.bind_pose == BindPose::Tree && !options.converts() => {
+            let rotation = quat_to_mat3(&Quat::from_vector(bone.rotation));
+            let trs = trs(bone.position.xyz(), &rotation, bone.scale.xyz());
+            (None, Some(trs))
+        }
+        Transform::Trs => (None, Some(decompose(local))),
+    }
+}
+
+/// Profile of the `<extra>` techniques that keep the fields COLLADA has no place for.
+const EXTRA_PROFILE: &str = "dstsmodel";
+
+fn bone_extra(bone: &Bone) -> Extra {
+    Extra {
+        profile: EXTRA_PROFILE.to_owned(),
+        elements: vec![
+            XmlElement::text("name_hash", format!("{:08X}", bone.name_hash)),
+            XmlElement::text("flags", format!("{:04X}", bone.flags)),
+        ],
+    }
+}
+
+/// The bone map goes with the skin when there is one, see [`skin_extra`].
+fn mesh_extra(mesh: &Mesh, binding: Binding) -> Extra {
+    let vec3_text = |v: &Vec3| format!("{} {} {}", v.x, v.y, v.z);
+    let attrs = mesh.attrs.iter().map(|attr| XmlElement {
+        name: "attr".to_owned(),
+        attributes: [
+            ("vtype", attr.vtype as u32),
+            ("normalize", attr.normalize as u32),
+            ("num", attr.num as u32),
+            ("dtype", attr.dtype as u32),
+            ("flags", attr.flags as u32),
+            ("offset", attr.offset as u32),
+        ]
+        .map(|(name, value)| (name.to_owned(), value.to_string()))
+        .into(),
+        children: vec![],
+        text: String::new(),
+    });
+    let bone_map = match binding {
+        Binding::Skinned => None,
+        _ => Some(bone_map_element(mesh)),
+    };
+    Extra {
+        profile: EXTRA_PROFILE.to_owned(),
+        elements: [
+            XmlElement::text("name_hash", format!("{:08X}", mesh.name_hash)),
+            XmlElement::text("material_id", mesh.material_id),
+            XmlElement::text("unk30", mesh.unk30),
+            XmlElement::text("unk31", mesh.unk31),
+            XmlElement::text("index_size", mesh.index_size),
+            XmlElement::text("vertex_size", mesh.vertex_size),
+            XmlElement::text("radius", mesh.radius),
+            XmlElement::text("center", vec3_text(&mesh.center)),
+            XmlElement::text("bound", vec3_text(&mesh.bound)),
+        ]
+        .into_iter()
+        .chain(bone_map)
+        .chain(attrs)
+        .collect(),
+    }
+}
+
+fn bone_map_element(mesh: &Mesh) -> XmlElement {
+    let bone_map: Vec<String> = mesh.bone_map.iter().map(|b| b.to_string()).collect();
+    XmlElement::text("bone_map", bone_map.join(" "))
+}
+
+fn skin_extra(mesh: &Mesh) -> Extra {
+    Extra {
+        profile: EXTRA_PROFILE.to_owned(),
+        elements: vec![bone_map_element(mesh)],
+    }
+}
+
+fn create_node(
+    bones: &[Bone],
+    locals: &[Mat4x4],
+    index: u16,
+    ids: &Ids,
+    options: &Options,
+    attachments: &mut [Vec<Node>],
+) -> Node {
+    let bone = &bones[index as usize];
+    let (matrix, trs) = joint_transform(bone, &locals[index as usize], options);
+
+    let mut nodes: Vec<Node> = bone
+        .children
+        .iter()
+        .map(|i| create_node(bones, locals, *i, ids, options, attachments))
+        .collect();
+    nodes.append(&mut attachments[index as usize]);
+
+    let id = ids.bones[index as usize].clone();
+    Node {
+        sid: Some(id.clone()),
+        id,
+        name: match options.naming {
+            Naming::Index => format!("bone{}-{}", index, bone.name),
+            Naming::Name => bone.name.clone(),
+        },
+        type_: NodeType::Joint,
+        matrix,
+        trs,
+        transform_sids: vec![],
+        instances: vec![],
+        instance_controllers: vec![],
+        instance_geometries: vec![],
+        nodes,
+        extras: vec![bone_extra(bone)],
+    }
+}
+
+fn create_bone_root(
+    skeleton: &Skeleton,
+    locals: &[Mat4x4],
+    ids: &Ids,
+    options: &Options,
+    attachments: &mut [Vec<Node>],
+) -> Node {
+    let (matrix, trs) = match options.transform {
+        Transform::Matrix => (Some(identity()), None),
+        Transform::Trs => (None, Some(decompose(&identity()))),
+    };
+    Node {
+        id: "boneroot".to_owned(),
+        name: "boneroot".to_owned(),
+        sid: Some("boneroot".to_owned()),
+        type_: NodeType::Joint,
+        matrix,
+        trs,
+        transform_sids: vec![],
+        instances: vec![],
+        instance_controllers: vec![],
+        instance_geometries: vec![],
+        nodes: skeleton
+            .root_bones
+            .iter()
+            .map(|i| create_node(&skeleton.bones, locals, *i, ids, options, attachments))
+            .collect(),
+        extras: vec![],
+    }
+}
+
+/// Formats `time` as an ISO 8601 date and time in UTC, e.g. `2022-06-19T15:05:15Z`.
+fn format_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Days since 1970-01-01 to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn create_asset(options: &Options) -> Asset {
+    let time = match options.timestamp {
+        Timestamp::Fixed => UNIX_EPOCH + Duration::from_secs(1655651115),
+        Timestamp::Now => SystemTime::now(),
+    };
+    Asset {
+        contributor: Some(Contributor {
+            author: None,
+            authoring_tool: Some(format!("dstsmodel {}", env!("CARGO_PKG_VERSION"))),
+            comments: None,
+            copyright: None,
+            source_data: options.source_file.clone(),
+        }),
+        created: format_time(time),
+        keywords: options.keywords.clone(),
+        modified: format_time(time),
+        title: options.title.clone(),
+        unit: options
+            .unit
+            .or(options.source_unit)
+            .map(|unit| collada::Unit {
+                name: match unit {
+                    LengthUnit::Metre => "meter".to_owned(),
+                    LengthUnit::Centimetre => "centimeter".to_owned(),
+                },
+                meter: unit.meters(),
+            }),
+        up_axis: options.up_axis.map(|up| match up {
+            Up::Y => UpAxis::Y,
+            Up::Z => UpAxis::Z,
+        }),
+    }
+}
+
+/// Exports only the bone hierarchy, e.g. of a standalone skeleton file.
+///
+/// Fails on the options that only apply to meshes, as there are no inverse bind matrices or
+/// meshes to apply them to.
+pub fn export_skeleton(skeleton: &Skeleton, options: &Options) -> Result<Collada> {
+    options.check()?;
+    if options.bind_pose != BindPose::Tree {
+        bail!("--bind-from-inv needs the inverse bind matrices of a model")
+    }
+    if options.strips {
+        bail!("--strips only applies to meshes")
+    }
+    let ids = Ids::new(skeleton, &[], &[], options.naming);
+    let locals: Vec<Mat4x4> = joint_matrices(skeleton, &[], BindPose::Tree)
+        .iter()
+        .map(|m| options.convert(m))
+        .collect();
+    let mut attachments: Vec<Vec<Node>> = skeleton.bones.iter().map(|_| vec![]).collect();
+    let bone_root = create_bone_root(skeleton, &locals, &ids, options, &mut attachments);
+
+    Ok(Collada {
+        version: options.version,
+        format: options.format.clone(),
+        asset: create_asset(options),
+        libraries: vec![Library::VisualScenes {
+            visual_scenes: vec![VisualScene {
+                id: "scene".to_owned(),
+                nodes: vec![bone_root],
+            }],
+        }],
+        scene: Scene {
+            instance_visual_scene: "#scene".to_owned(),
+        },
+    })
+}
+
+pub fn export(model: &Model, options: &Options) -> Result<Collada> {
+    options.check()?;
+    let skeleton = &model.skeleton;
+    let ids = Ids::new(skeleton, &model.meshs, &model.materials, options.naming);
+    let mut controllers: Vec<Controller> = Vec::new();
+    let mut geometries: Vec<Geometry> = Vec::new();
+    let mut bindings: Vec<Binding> = Vec::new();
+    for (i, mesh) in model.meshs.iter().enumerate() {
+        let mesh_id = &ids.meshs[i];
+        let controller_id = &ids.controllers[i];
+        let texcoords = mesh.texcoords();
+
+        let mut bone_array: Vec<u8> = Vec::new();
+        let mut weight_array: Vec<f32> = Vec::new();
+        let mut bone_attr_num = 0;
+        let mut weight_attr_num = 0;
+        let mut vertex_sources: Vec<Source> = Vec::new();
+        let mut vertex_inputs: Vec<Input> = Vec::new();
+        let mut primitive_inputs: Vec<SharedInput> = vec![SharedInput {
+            semantic: "VERTEX".to_owned(),
+            source: format!("#{mesh_id}-vertices"),
+            offset: 0,
+            set: None,
+        }];
+        for (attr_i, attr) in mesh.attrs.iter().enumerate() {
+            let num = attr.components();
+            let mut data = mesh.decode(attr, num)?;
+            if attr.vtype == 10 {
+                bone_array = data.iter().map(|&b| b as u8).collect();
+                bone_attr_num = num;
+                continue;
+            }
+
+            if matches!(attr.vtype, 1..=3) && num >= 3 && options.converts() {
+                let mut m = options.axes();
+                if attr.vtype == 1 {
+                    m *= options.scale();
+                }
+                for v in data.chunks_exact_mut(num as usize) {
+                    let converted = m * vec3(v[0], v[1], v[2]);
+                    v[..3].copy_from_slice(converted.as_slice());
+                    // The sign of the bitangent
+                    if attr.vtype == 3 && num == 4 && options.mirrors() {
+                        v[3] = -v[3];
+                    }
+                }
+            }
+
+            if attr.vtype == 11 {
+                weight_attr_num = num;
+                weight_array = data;
+                continue;
+            }
+
+            if num > 4 {
+                bail!("Attribute too large");
+            }
+            let params = match attr.vtype {
+                1..=3 => &["X", "Y", "Z", "W"][0..num as usize],
+                5..=7 => &["S", "T", "U", "V"][0..num as usize],
+                9 => &["R", "G", "B", "A"][0..num as usize],
+                _ => bail!("Unknown vtype {}", attr.vtype),
+            };
+            let source_id = format!("{mesh_id}-attr{attr_i}");
+            let array_id = format!("{source_id}-array");
+            vertex_sources.push(Source {
+                id: source_id.clone(),
+                array_element: ArrayElement::FloatArray {
+                    id: array_id.clone(),
+                    array: data,
+                },
+                technique_common: TechniqueCommon {
+                    elements: vec![TechniqueCommonElement::Accessor {
+                        count: mesh.num_vertex,
+                        source: format!("#{array_id}"),
+                        stride: num as u32,
+                        params: params
+                            .iter()
+                            .map(|&s| Param {
+                                name: s.to_owned(),
+                                type_: "float".to_owned(),
+                            })
+                            .collect(),
+                    }],
+                },
+            });
+            match attr.vtype {
+                1 => {
+                    vertex_inputs.push(Input {
+                        semantic: "POSITION".to_owned(),
+                        source: format!("#{source_id}"),
+                    });
+                }
+                2 => primitive_inputs.push(SharedInput {
+                    semantic: "NORMAL".to_owned(),
+                    source: format!("#{source_id}"),
+                    offset: 0,
+                    set: None,
+                }),
+                3 => primitive_inputs.push(SharedInput {
+                    semantic: "TANGENT".to_owned(),
+                    source: format!("#{source_id}"),
+                    offset: 0,
+                    set: None,
+                }),
+                5..=7 => primitive_inputs.push(SharedInput {
+                    semantic: "TEXCOORD".to_owned(),
+                    source: format!("#{source_id}"),
+                    offset: 0,
+                    set: texcoords
+                        .iter()
+                        .position(|t| t.vtype == attr.vtype)
+                        .map(|set| set as u32),
+                }),
+                9 => primitive_inputs.push(SharedInput {
+                    semantic: "COLOR".to_owned(),
+                    source: format!("#{source_id}"),
+                    offset: 0,
+                    set: None,
+                }),
+
+                _ => bail!("Unknown vtype {}", attr.vtype),
+            }
+        }
+
+        let material = model
+            .material(mesh)
+            .map(|_| ids.materials[mesh.material_id as usize].clone());
+        let primitive = if options.strips {
+            let mut strips = mesh.strips();
+            if options.mirrors() {
+                // A leading degenerate triangle flips the winding of the rest of the strip.
+                for strip in &mut strips {
+                    strip.insert(0, strip[0]);
+                }
+            }
+            PrimitiveElements::Tristrips {
+                count: strips.len() as u32,
+                material,
+                inputs: primitive_inputs,
+                p: strips,
+            }
+        } else {
+            let mut unfold_indices = mesh.triangles();
+            if options.mirrors() {
+                for triangle in unfold_indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+            PrimitiveElements::Triangles {
+                count: unfold_indices.len() as u32 / 3,
+                material,
+                inputs: primitive_inputs,
+                p: unfold_indices,
+            }
+        };
+
+        if weight_attr_num != bone_attr_num {
+            bail!("Mismatched bone num");
+        }
+
+        // A single-bone palette can't deform anything, and a mesh without weights can only follow
+        // the first bone of its palette, if any.
+        let binding = match mesh.bone_map.as_slice() {
+            [bone] => Binding::Rigid(*bone),
+            [] if weight_attr_num == 0 => Binding::Static,
+            [bone, ..] if weight_attr_num == 0 => Binding::Rigid(*bone),
+            _ => Binding::Skinned,
+        };
+
+        geometries.push(Geometry {
+            id: mesh_id.clone(),
+            name: None,
+            geometric_element: GeometricElement::Mesh {
+                sources: vertex_sources,
+                vertices: Vertices {
+                    id: format!("{mesh_id}-vertices"),
+                    inputs: vertex_inputs,
+                },
+                primitive_elements: vec![primitive],
+            },
+            extras: vec![mesh_extra(mesh, binding)],
+        });
+        bindings.push(binding);
+        if !matches!(binding, Binding::Skinned) {
+            continue;
+        }
+
+        controllers.push(Controller {
+            id: controller_id.clone(),
+            control_element: ControlElement::Skin(Skin {
+                source: format!("#{mesh_id}"),
+                bind_shape_matrix: None,
+                sources: vec![
+                    Source {
+                        id: format!("{controller_id}-joint"),
+                        array_element: ArrayElement::NameArray {
+                            id: format!("{controller_id}-joint-array"),
+                            array: mesh
+                                .bone_map
+                                .iter()
+                                .map(|&j| ids.bones[j as usize].clone())
+                                .collect(),
+                        },
+                        technique_common: TechniqueCommon {
+                            elements: vec![TechniqueCommonElement::Accessor {
+                                count: mesh.bone_map.len() as u32,
+                                source: format!("#{controller_id}-joint-array"),
+                                stride: 1,
+                                params: vec![Param {
+                                    name: "JOINT".to_owned(),
+                                    type_: "name".to_owned(),
+                                }],
+                            }],
+                        },
+                    },
+                    Source {
+                        id: format!("{controller_id}-inv"),
+                        array_element: ArrayElement::FloatArray {
+                            id: format!("{controller_id}-inv-array"),
+                            array: mesh
+                                .bone_map
+                                .iter()
+                                .flat_map(|&j| {
+                                    let m = options.convert(&model.inv_matrixs[j as usize]);
+                                    m.row_iter().flatten().copied().collect::<Vec<_>>()
+                                })
+                                .collect(),
+                        },
+                        technique_common: TechniqueCommon {
+                            elements: vec![TechniqueCommonElement::Accessor {
+                                count: mesh.bone_map.len() as u32,
+                                source: format!("#{controller_id}-inv-array"),
+                                stride: 16,
+                                params: vec![Param {
+                                    name: "TRANSFORM".to_owned(),
+                                    type_: "float4x4".to_owned(),
+                                }],
+                            }],
+                        },
+                    },
+                    Source {
+                        id: format!("{controller_id}-weight"),
+                        array_element: ArrayElement::FloatArray {
+                            id: format!("{controller_id}-weight-array"),
+                            array: weight_array,
+                        },
+                        technique_common: TechniqueCommon {
+                            elements: vec![TechniqueCommonElement::Accessor {
+                                count: mesh.num_vertex * bone_attr_num as u32,
+                                source: format!("#{controller_id}-weight-array"),
+                                stride: 1,
+                                params: vec![Param {
+                                    name: "WEIGHT".to_owned(),
+                                    type_: "float".to_owned(),
+                                }],
+                            }],
+                        },
+                    },
+                ],
+                joints: Joints {
+                    inputs: vec![
+                        Input {
+                            semantic: "JOINT".to_owned(),
+                            source: format!("#{controller_id}-joint"),
+                        },
+                        Input {
+                            semantic: "INV_BIND_MATRIX".to_owned(),
+                            source: format!("#{controller_id}-inv"),
+                        },
+                    ],
+                },
+                vertex_weights: VertexWeights {
+                    count: mesh.num_vertex,
+                    inputs: vec![
+                        SharedInput {
+                            semantic: "JOINT".to_owned(),
+                            source: format!("#{controller_id}-joint"),
+                            offset: 0,
+                            set: None,
+                        },
+                        SharedInput {
+                            semantic: "WEIGHT".to_owned(),
+                            source: format!("#{controller_id}-weight"),
+                            offset: 1,
+                            set: None,
+                        },
+                    ],
+                    vcount: vec![bone_attr_num as u8; mesh.num_vertex as usize],
+                    v: bone_array
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(j, &b)| [b as u32, j as u32])
+                        .collect(),
+                },
+            }),
+            extras: vec![skin_extra(mesh)],
+        })
+    }
+
+    let mut attachments: Vec<Vec<Node>> = skeleton.bones.iter().map(|_| vec![]).collect();
+    let mut mesh_nodes: Vec<Node> = Vec::new();
+    for (i, (mesh, binding)) in model.meshs.iter().zip(&bindings).enumerate() {
+        let mesh_id = &ids.meshs[i];
+        let controller_id = &ids.controllers[i];
+        let bind_material = || {
+            model.material(mesh).map(|_| {
+                let material_id = &ids.materials[mesh.material_id as usize];
+                BindMaterial {
+                    instance_materials: vec![InstanceMaterial {
+                        symbol: material_id.clone(),
+                        target: format!("#{material_id}"),
+                    }],
+                }
+            })
+        };
+        let mut node = Node {
+            id: format!("{mesh_id}-node"),
+            name: match options.naming {
+                Naming::Index => format!("mesh{}-{}", i, mesh.name),
+                Naming::Name => mesh.name.clone(),
+            },
+            sid: Some(format!("{mesh_id}-node")),
+            type_: NodeType::Node,
+            matrix: None,
+            trs: None,
+            transform_sids: vec![],
+            instances: vec![],
+            instance_controllers: vec![],
+            instance_geometries: vec![],
+            nodes: vec![],
+            extras: vec![],
+        };
+        match *binding {
+            Binding::Static => {
+                node.instance_geometries.push(InstanceGeometry {
+                    url: format!("#{mesh_id}"),
+                    bind_material: bind_material(),
+                });
+                mesh_nodes.push(node);
+            }
+            Binding::Rigid(bone) => {
+                // Vertices are in model space, so undo the bone's bind pose.
+                node.matrix = Some(options.convert(&model.inv_matrixs[bone as usize]));
+                node.instance_geometries.push(InstanceGeometry {
+                    url: format!("#{mesh_id}"),
+                    bind_material: bind_material(),
+                });
+                attachments[bone as usize].push(node);
+            }
+            Binding::Skinned => {
+                node.instance_controllers.push(InstanceController {
+                    url: format!("#{controller_id}"),
+                    skeletons: vec!["#boneroot".to_owned()],
+                    bind_material: bind_material(),
+                });
+                mesh_nodes.push(node);
+            }
+        }
+    }
+
+    let locals: Vec<Mat4x4> = joint_matrices(skeleton, &model.inv_matrixs, options.bind_pose)
+        .iter()
+        .map(|m| options.convert(m))
+        .collect();
+    let bone_root = create_bone_root(skeleton, &locals, &ids, options, &mut attachments);
+
+    let mut nodes = vec![bone_root];
+    nodes.append(&mut mesh_nodes);
+
+    let mut effects: Vec<Effect> = Vec::new();
+    let mut materials: Vec<collada::Material> = Vec::new();
+    for (i, material) in model.materials.iter().enumerate() {
+        let material_id = &ids.materials[i];
+        let name = match &material.name {
+            Some(name) => name.clone(),
+            None => format!("material{i}"),
+        };
+        effects.push(Effect {
+            id: format!("{material_id}-effect"),
+            name: name.clone(),
+            diffuse: [0.8, 0.8, 0.8, 1.0],
+        });
+        materials.push(collada::Material {
+            id: material_id.clone(),
+            name,
+            instance_effect: format!("#{material_id}-effect"),
+        });
+    }
+
+    let visual_scene = VisualScene {
+        id: "scene".to_owned(),
+        nodes,
+    };
+
+    let mut dae = Collada {
+        version: options.version,
+        format: options.format.clone(),
+        asset: create_asset(options),
+        libraries: vec![
+            Library::Geometries { geometries },
+            Library::VisualScenes {
+                visual_scenes: vec![visual_scene],
+            },
+        ],
+        scene: Scene {
+            instance_visual_scene: "#scene".to_owned(),
+        },
+    };
+
+    // Libraries must not be empty
+    if !controllers.is_empty() {
+        dae.libraries.push(Library::Controllers { controllers });
+    }
+    if !materials.is_empty() {
+        dae.libraries.push(Library::Effects { effects });
+        dae.libraries.push(Library::Materials { materials });
+    }
+
+    Ok(dae)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_ids_avoid_derived_ids() {
+        let mut model = model::test_model();
+        model.skeleton.bones[0].name = "body-attr0".to_owned();
+        model.skeleton.bones[1].name = "body-skin-weight-array".to_owned();
+        let ids = Ids::new(
+            &model.skeleton,
+            &model.meshs,
+            &model.materials,
+            Naming::Name,
+        );
+        assert_eq!(ids.bones, ["body-attr0", "body-skin-weight-array"]);
+        assert_eq!(ids.meshs, ["body_1"]);
+        assert_eq!(ids.controllers, ["body_1-skin"]);
+
+        let options = Options {
+            naming: Naming::Name,
+            ..Options::default()
+        };
+        let xml = export(&model, &options).unwrap().to_string().unwrap();
+        let mut seen = HashSet::new();
+        for id in xml.split(" id=\"").skip(1) {
+            let id = &id[..id.find('"').unwrap()];
+            assert!(seen.insert(id), "{id} used twice in\n{xml}");
+        }
+    }
+
+    #[test]
+    fn skeleton_export_rejects_mesh_options() {
+        let skeleton = &model::test_model().skeleton;
+        assert!(export_skeleton(skeleton, &Options::default()).is_ok());
+        let options = Options {
+            bind_pose: BindPose::InverseBind,
+            ..Options::default()
+        };
+        assert!(export_skeleton(skeleton, &options).is_err());
+        let options = Options {
+            strips: true,
+            ..Options::default()
+        };
+        assert!(export_skeleton(skeleton, &options).is_err());
+    }
+
+    #[test]
+    fn writes_to_a_buffer() {
+        let dae = export(&model::test_model(), &Options::default()).unwrap();
+        let mut buffer: Vec<u8> = Vec::new();
+        dae.write_to(&mut buffer).unwrap();
+        let xml = String::from_utf8(buffer).unwrap();
+        assert_eq!(xml, dae.to_string().unwrap());
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<COLLADA "));
+        for expected in [
+            r#"<COLLADA xmlns="http://www.collada.org/2008/03/COLLADASchema" version="1.5.0">"#,
+            r#"<float_array id="mesh0-attr0-array" count="12">0 0 0 1 0 0 0 1 0 1 1 0</float_array>"#,
+            r#"<triangles material="material0" count="2">"#,
+            "<p>0 1 2 2 1 3</p>",
+            r#"<node id="bone1" name="bone1-arm" sid="bone1" type="JOINT">"#,
+            r#"<Name_array id="controller0-joint-array" count="2">bone0 bone1</Name_array>"#,
+            "<bone_map>0 1</bone_map>",
+            r#"<material id="material0" name="skin">"#,
+        ] {
+            assert!(xml.contains(expected), "{expected} missing from\n{xml}");
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use crate::model::*;
+use anyhow::Result;
+use serde_json::{Value, json};
+
+/// The whole model as JSON. Each mesh also gets a `decoded` array with the values of every
+/// attribute in `attrs`, vertex after vertex, and keeps its raw `vertexs` only if `raw` is set.
+pub fn to_json(model: &Model, raw: bool) -> Result<Value> {
+    let mut value = serde_json::to_value(model)?;
+    let Some(meshs) = value["meshs"].as_array_mut() else {
+        return Ok(value);
+    };
+    for (mesh, json) in model.meshs.iter().zip(meshs) {
+        let decoded = mesh
+            .attrs
+            .iter()
+            .map(|attr| mesh.decode(attr, attr.num))
+            .collect::<Result<Vec<_>>>()?;
+        let json = json.as_object_mut().unwrap();
+        json.insert("decoded".to_owned(), json!(decoded));
+        if !raw {
+            json.remove("vertexs");
+        }
+    }
+    Ok(value)
+}
@@ -0,0 +1,136 @@
+use crate::model::*;
+use anyhow::{Result, bail};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{File, read_to_string};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+/// Hash of a bone, mesh or material name, as stored in the `name_hash` fields.
+///
+/// This is the standard CRC-32 (the one of zlib and PNG) over the bytes of the name. That is not
+/// confirmed by a name from the game's files yet, see the `sample_model_hashes` test. Names whose
+/// stored hash disagrees are reported by [`Model::hash_mismatches`].
+pub fn name_hash(name: &str) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for &b in name.as_bytes() {
+        crc = CRC32_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// A persistent hash to name dictionary, stored as lines of `XXXXXXXX name`.
+///
+/// A hash can map to multiple names in case of collisions.
+#[derive(Default)]
+pub struct HashDict {
+    pub names: BTreeMap<u32, BTreeSet<String>>,
+}
+
+impl HashDict {
+    pub fn load(path: &Path) -> Result<HashDict> {
+        let mut dict = HashDict::default();
+        for (i, line) in read_to_string(path)?.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let Some((hash, name)) = line.split_once(' ') else {
+                bail!("{}:{}: Expected a hash and a name", path.display(), i + 1)
+            };
+            dict.insert(u32::from_str_radix(hash, 16)?, name);
+        }
+        Ok(dict)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        for (hash, names) in &self.names {
+            for name in names {
+                writeln!(file, "{hash:08X} {name}")?;
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Adds a name under `hash`. Empty names, left by missing string-table entries, are skipped.
+    pub fn insert(&mut self, hash: u32, name: &str) {
+        if name.is_empty() {
+            return;
+        }
+        self.names.entry(hash).or_default().insert(name.to_owned());
+    }
+
+    /// Adds every bone, mesh and material name of a model, keyed by the hash stored with it.
+    pub fn add_model(&mut self, model: &Model) {
+        for bone in &model.skeleton.bones {
+            self.insert(bone.name_hash, &bone.name);
+        }
+        for mesh in &model.meshs {
+            self.insert(mesh.name_hash, &mesh.name);
+        }
+        for material in &model.materials {
+            if let Some(name) = &material.name {
+                self.insert(material.name_hash, name);
+            }
+        }
+        // Names not claimed by any material still go in, under their computed hash.
+        for name in &model.material_names {
+            self.insert(name_hash(name), name);
+        }
+    }
+
+    pub fn lookup(&self, hash: u32) -> Option<&BTreeSet<String>> {
+        self.names.get(&hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(name_hash("123456789"), 0xCBF43926);
+        assert_eq!(name_hash(""), 0);
+    }
+
+    /// No game files can be part of the repository, so this runs on one given by
+    /// `DSTSMODEL_SAMPLE=<model> cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn sample_model_hashes() {
+        let path = std::env::var("DSTSMODEL_SAMPLE").expect("DSTSMODEL_SAMPLE is not set");
+        let model = Model::read(&mut File::open(path).unwrap()).unwrap();
+        let named = model.meshs.iter().filter(|m| !m.name.is_empty()).count()
+            + model
+                .skeleton
+                .bones
+                .iter()
+                .filter(|b| !b.name.is_empty())
+                .count();
+        assert!(named > 0, "The sample has no names to check");
+        assert_eq!(model.hash_mismatches(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,851 @@
+use crate::file_ext::*;
+use crate::hash::*;
+use crate::skeleton::*;
+use anyhow::{Result, bail};
+use nalgebra_glm::*;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Header {
+    pub magic: u32,
+    pub num_mesh: u16,
+    pub num_material: u16,
+    pub num_light: u16,
+    pub num_camera: u16,
+    pub num_bones: u16,
+    pub num_unk: u16,
+
+    pub center: Vec3,
+    pub bound: Vec3,
+
+    pub unk30: u32,
+    pub unk38: u32,
+
+    pub material_id: u32,
+    pub unk44: u32,
+    pub offset_mesh: u64, // 0x356284 ~ 0x358140
+    pub offset_material: u64,
+    pub offset_light: u64,  // 0
+    pub offset_camera: u64, // 0
+    pub offset_inv_matrix: u64,
+    pub offset_unk70: u64, // 0
+    pub offset_string_table: u64,
+    pub offset_unk80: u64, // unexplored, 0x3588c0 ~ 0x358cdc
+    pub offset_unk88: u64, // 0
+    pub offset_name: u64,
+    pub offset_tree: u64,
+    pub offset_unka0: u64, // 0
+}
+
+impl Header {
+    pub fn read(file: &mut (impl Read + Seek)) -> Result<Header> {
+        let magic = file.read_u32()?;
+        let num_mesh = file.read_u16()?;
+        let num_material = file.read_u16()?;
+        let num_light = file.read_u16()?;
+        let num_camera = file.read_u16()?;
+        let num_bones = file.read_u16()?;
+        let num_unk = file.read_u16()?;
+
+        let zero10 = file.read_u32()?;
+        let center = file.read_f32vec3()?;
+        let bound = file.read_f32vec3()?;
+        let zero2c = file.read_u32()?;
+
+        let unk30 = file.read_u32()?;
+        let zero34 = file.read_u32()?;
+        let unk38 = file.read_u32()?;
+        let zero3c = file.read_u32()?;
+
+        let material_id = file.read_u32()?;
+        let unk44 = file.read_u32()?;
+        let offset_mesh = file.read_u64()?;
+        let offset_material = file.read_u64()?;
+        let offset_light = file.read_u64()?;
+        let offset_camera = file.read_u64()?;
+        let offset_inv_matrix = file.read_u64()?;
+        let offset_unk70 = file.read_u64()?;
+        let offset_string_table = file.read_u64()?;
+        let offset_unk80 = file.read_u64()?;
+        let offset_unk88 = file.read_u64()?;
+        let offset_name = file.read_u64()?;
+        let offset_tree = file.read_u64()?;
+        let offset_unka0 = file.read_u64()?;
+
+        if num_light != 0 {
+            bail!("Can't deal with light")
+        }
+        if num_camera != 0 {
+            bail!("Can't deal with light")
+        }
+        if num_unk != 0 {
+            bail!("Can't deal with num_unk")
+        }
+        if zero10 != 0 {
+            bail!("Can't deal with unk10")
+        }
+        if zero2c != 0 {
+            bail!("Can't deal with unk2c")
+        }
+        /*if unk30 != 0 {
+            bail!("Can't deal with unk30")
+        }*/
+        if zero34 != 0 {
+            bail!("Can't deal with unk34")
+        }
+        /*if unk38 != 0 {
+            bail!("Can't deal with unk38")
+        }*/
+        if zero3c != 0 {
+            bail!("Can't deal with unk3c")
+        }
+
+        // Note on offset_unk80
+        //   This data appears to be 16 (potentially useful?) bytes followed by every byte value repeated x4,
+        //   then 12 bytes of something
+
+        Ok(Header {
+            magic,
+            num_mesh,
+            num_material,
+            num_light,
+            num_camera,
+            num_bones,
+            num_unk,
+            center,
+            bound,
+            unk30,
+            unk38,
+            material_id,
+            unk44,
+            offset_mesh,
+            offset_material,
+            offset_light,
+            offset_camera,
+            offset_inv_matrix,
+            offset_unk70,
+            offset_string_table,
+            offset_unk80,
+            offset_unk88,
+            offset_name,
+            offset_tree,
+            offset_unka0,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Attr {
+    pub vtype: u8,
+    pub normalize: u8,
+    pub num: u16,
+    pub dtype: u8,
+    pub flags: u8,
+    pub offset: u16,
+}
+
+impl Attr {
+    /// The number of components worth reading. Positions are sometimes padded to 4.
+    pub fn components(&self) -> u16 {
+        if self.vtype == 1 && self.num > 3 {
+            3
+        } else {
+            self.num
+        }
+    }
+}
+
+/// The width in bytes of `num_index` indices taking up `index_bytes`, padding included.
+///
+/// The index buffer is directly followed by the attribute descriptors (aligned to 4), so its byte
+/// size tells the index width. With one index or none both widths pad to the same size; those
+/// meshes are taken as 16-bit, which doesn't matter as they can't hold a triangle.
+fn index_size(num_index: u32, index_bytes: u64) -> Option<u8> {
+    if align_up(num_index as u64 * 2, 4) == index_bytes {
+        Some(2)
+    } else if align_up(num_index as u64 * 4, 4) == index_bytes {
+        Some(4)
+    } else {
+        None
+    }
+}
+
+/// Reads `num_index` little-endian indices of `index_size` bytes.
+fn read_indexs(file: &mut impl Read, num_index: u32, index_size: u8) -> Result<Vec<u32>> {
+    (0..num_index)
+        .map(|_| match index_size {
+            2 => Ok(file.read_u16()? as u32),
+            _ => file.read_u32(),
+        })
+        .collect()
+}
+
+/// Converts a triangle strip into a triangle list.
+///
+/// `restart` starts a new strip, which also resets the winding parity. Degenerate triangles, used
+/// to stitch strips together, are dropped but still count towards the parity.
+pub fn unfold_strip(indexs: &[u32], restart: u32) -> Vec<u32> {
+    let mut triangles: Vec<u32> = vec![];
+    for strip in indexs.split(|&i| i == restart) {
+        for (k, window) in strip.windows(3).enumerate() {
+            let (a, b, c) = (window[0], window[1], window[2]);
+            if a == b || b == c || a == c {
+                continue;
+            }
+            if k % 2 == 0 {
+                triangles.extend_from_slice(&[a, b, c])
+            } else {
+                triangles.extend_from_slice(&[b, a, c])
+            }
+        }
+    }
+    triangles
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Mesh {
+    pub offset_vertex: u64,
+    pub offset_index: u64,
+    pub offset_bone_map: u64,
+    pub offset_unk: u64,
+    pub offset_attr: u64,
+    pub num_bone: u16,
+    pub num_attr: u16,
+    pub vertex_size: u32,
+    pub unk30: u8, // 0, 2, 3, 4
+    pub unk31: u8, // 5, 1
+    pub name_hash: u32,
+    pub name_offset: u64,
+    pub material_id: u32,
+    pub num_vertex: u32,
+    pub num_index: u32,
+    pub radius: f32,
+    pub center: Vec3,
+    pub bound: Vec3,
+
+    /// Width of an index in bytes, either 2 or 4.
+    pub index_size: u8,
+
+    /// Raw vertex data, serialized as one base64 string per vertex. Positions are in model space,
+    /// not relative to a bone.
+    #[serde(with = "base64_vertexs", default)]
+    pub vertexs: Vec<Vec<u8>>,
+    pub bone_map: Vec<u32>,
+    pub indexs: Vec<u32>,
+    pub attrs: Vec<Attr>,
+
+    pub name: String,
+}
+
+mod base64_vertexs {
+    use base64::prelude::*;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(vertexs: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(vertexs.iter().map(|v| BASE64_STANDARD.encode(v)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        Vec::<String>::deserialize(deserializer)?
+            .iter()
+            .map(|v| BASE64_STANDARD.decode(v).map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+impl Mesh {
+    /// The index value that restarts a strip, i.e. all bits set for the index width.
+    pub fn restart_index(&self) -> u32 {
+        if self.index_size == 2 {
+            0xFFFF
+        } else {
+            0xFFFFFFFF
+        }
+    }
+
+    pub fn attr(&self, vtype: u8) -> Option<&Attr> {
+        self.attrs.iter().find(|a| a.vtype == vtype)
+    }
+
+    /// The texture coordinate attributes, in the order of their sets.
+    pub fn texcoords(&self) -> Vec<&Attr> {
+        let mut texcoords: Vec<&Attr> = self
+            .attrs
+            .iter()
+            .filter(|a| matches!(a.vtype, 5..=7))
+            .collect();
+        texcoords.sort_by_key(|a| match a.vtype {
+            6 => 0,
+            7 => 1,
+            _ => 2,
+        });
+        texcoords
+    }
+
+    /// Decodes the first `num` components of an attribute of every vertex, one after the other.
+    /// u8 components are normalized to 0..1, except for bone indices which keep their value.
+    pub fn decode(&self, attr: &Attr, num: u16) -> Result<Vec<f32>> {
+        let component = |v: &[u8], j: u16| -> f32 {
+            match attr.dtype {
+                0 if attr.vtype == 10 => v[(attr.offset + j) as usize] as f32,
+                0 => v[(attr.offset + j) as usize] as f32 / 255.0,
+                8 => half::f16::from_le_bytes(
+                    v[(attr.offset + j * 2) as usize..][..2].try_into().unwrap(),
+                )
+                .to_f32(),
+                _ => {
+                    f32::from_le_bytes(v[(attr.offset + j * 4) as usize..][..4].try_into().unwrap())
+                }
+            }
+        };
+        if !matches!(attr.dtype, 0 | 8 | 9) {
+            bail!("Unknown dtype {}", attr.dtype)
+        }
+        Ok(self
+            .vertexs
+            .iter()
+            .flat_map(|v| (0..num).map(move |j| component(v, j)))
+            .collect())
+    }
+
+    /// Splits a strip index buffer at restart indices, dropping strips too short to hold a
+    /// triangle.
+    pub fn strips(&self) -> Vec<Vec<u32>> {
+        let restart = self.restart_index();
+        self.indexs
+            .split(|&i| i == restart)
+            .filter(|strip| strip.len() >= 3)
+            .map(|strip| strip.to_vec())
+            .collect()
+    }
+
+    /// Returns the index buffer as a triangle list.
+    ///
+    /// Every index buffer seen so far is a strip. No value of `unk30`/`unk31` has been tied to
+    /// triangle lists yet.
+    pub fn triangles(&self) -> Vec<u32> {
+        unfold_strip(&self.indexs, self.restart_index())
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Material {
+    pub name_hash: u32,
+    /// Resolved from the material name table, if it lines up with the materials.
+    pub name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Model {
+    pub header: Header,
+    pub meshs: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    /// The material name table as stored, which doesn't always have one entry per material.
+    pub material_names: Vec<String>,
+    pub inv_matrixs: Vec<Mat4x4>,
+    pub skeleton: Skeleton,
+}
+
+impl Model {
+    /// The material a mesh is drawn with. `material_id` is taken as an index into the material
+    /// table, which every sample so far is consistent with but nothing in the file confirms;
+    /// `None` when it's out of range, which `validate` reports.
+    pub fn material(&self, mesh: &Mesh) -> Option<&Material> {
+        self.materials.get(mesh.material_id as usize)
+    }
+
+    pub fn read(file: &mut (impl Read + Seek)) -> Result<Model> {
+        let header = Header::read(file)?;
+        let num_bones = header.num_bones;
+
+        file.seek_noop(header.offset_name)?;
+
+        let num_bone_name = file.read_u32()?;
+        let num_material_name = file.read_u32()?;
+        let offset_bone_name = file.read_u64()?;
+        let offset_material_name = file.read_u64()?;
+        if num_bone_name != num_bones as u32 {
+            bail!("num_bone_name");
+        }
+        // `num_material_name` can differ from `num_material` (chr042). Which names and materials
+        // are left over is reported by `unpaired_materials`.
+        let mut bone_material_offset: Vec<u64> = Vec::new();
+        for _ in 0..num_bones {
+            bone_material_offset.push(file.read_u64()?)
+        }
+        let mut material_name_offset: Vec<u64> = Vec::new();
+        for _ in 0..num_material_name {
+            material_name_offset.push(file.read_u64()?)
+        }
+
+        file.seek_noop(header.offset_mesh)?;
+
+        let mut meshs: Vec<Mesh> = Vec::new();
+        for i in 0..header.num_mesh {
+            let offset_vertex = file.read_u64()?;
+            let offset_index = file.read_u64()?;
+
+            let offset_bone_map = file.read_u64()?;
+            let offset_unk = file.read_u64()?;
+
+            let offset_attr = file.read_u64()?;
+            let num_bone = file.read_u16()?;
+            let num_attr = file.read_u16()?;
+            let vertex_size = file.read_u32()?;
+
+            let unk30 = file.read_u8()?;
+            let unk31 = file.read_u8()?;
+            let zero32 = file.read_u16()?; // 0
+            let name_hash = file.read_u32()?;
+            let name_offset = file.read_u64()?;
+
+            let material_id = file.read_u32()?;
+            let num_vertex = file.read_u32()?;
+            let num_index = file.read_u32()?;
+            let zero4c = file.read_u32()?; // 0
+
+            let zero50 = file.read_u32()?; // 0
+            let radius = file.read_f32()?;
+            let center = file.read_f32vec3()?;
+            let bound = file.read_f32vec3()?;
+
+            let zero70 = file.read_u32()?;
+            let zero74 = file.read_u32()?;
+            let zero78 = file.read_u32()?;
+            let zero7c = file.read_u32()?;
+
+            if zero32 != 0 {
+                bail!("Can't deal with unk32")
+            }
+            if zero4c != 0 {
+                bail!("Can't deal with unk4c")
+            }
+            if zero50 != 0 {
+                bail!("Can't deal with unk50")
+            }
+            if zero70 != 0 {
+                bail!("Can't deal with unk70")
+            }
+            if zero74 != 0 {
+                bail!("Can't deal with unk74")
+            }
+            if zero78 != 0 {
+                bail!("Can't deal with unk78")
+            }
+            if zero7c != 0 {
+                bail!("Can't deal with unk7c")
+            }
+
+            let index_bytes = offset_attr.saturating_sub(offset_index);
+            let Some(index_size) = index_size(num_index, index_bytes) else {
+                bail!(
+                    "Can't determine index size of mesh {i}: {num_index} indices in {index_bytes} bytes"
+                )
+            };
+
+            let mesh = Mesh {
+                offset_vertex,
+                offset_index,
+                offset_bone_map,
+                offset_unk,
+                offset_attr,
+                num_bone,
+                num_attr,
+                vertex_size,
+                unk30,
+                unk31,
+                name_hash,
+                name_offset,
+                material_id,
+                num_vertex,
+                num_index,
+                radius,
+                center,
+                bound,
+                index_size,
+                ..Mesh::default()
+            };
+
+            meshs.push(mesh)
+        }
+
+        for (i, mesh) in meshs.iter_mut().enumerate() {
+            file.seek_noop(mesh.offset_vertex)?;
+            for _ in 0..mesh.num_vertex {
+                let mut vertex = vec![0u8; mesh.vertex_size as usize];
+                file.read_exact(&mut vertex)?;
+                mesh.vertexs.push(vertex);
+            }
+
+            file.seek_noop(mesh.offset_bone_map)?;
+            for _ in 0..mesh.num_bone {
+                mesh.bone_map.push(file.read_u32()?);
+            }
+
+            file.seek_noop(mesh.offset_index)?;
+            mesh.indexs = read_indexs(file, mesh.num_index, mesh.index_size)?;
+
+            file.seek_assert_align_up(mesh.offset_attr, 4)?;
+            for _ in 0..mesh.num_attr {
+                let vtype = file.read_u8()?;
+                let normalize = file.read_u8()?;
+                let num = file.read_u16()?;
+                let dtype = file.read_u8()?;
+                let flags = file.read_u8()?;
+                let offset = file.read_u16()?;
+                if normalize != 0 {
+                    bail!("Can't deal with normalize")
+                }
+                if flags != 0 {
+                    bail!("Can't deal with flags")
+                }
+                // println!("[{i}] -- {vtype} {normalize} {num} {dtype} {flags}");
+                mesh.attrs.push(Attr {
+                    vtype,
+                    normalize,
+                    num,
+                    dtype,
+                    flags,
+                    offset,
+                })
+            }
+        }
+
+        file.seek_noop(header.offset_material)?;
+        let mut materials: Vec<Material> = Vec::new();
+        for _ in 0..header.num_material {
+            let name_hash = file.read_u32()?;
+            //...
+            materials.push(Material {
+                name_hash,
+                ..Material::default()
+            })
+        }
+
+        file.seek(SeekFrom::Start(header.offset_inv_matrix))?;
+        let mut inv_matrixs: Vec<Mat4x4> = Vec::new();
+        for _ in 0..num_bones {
+            inv_matrixs.push(file.read_f32m3x4()?);
+        }
+
+        file.seek(SeekFrom::Start(header.offset_tree))?;
+        let mut skeleton = Skeleton::read(file)?;
+        if skeleton.bones.len() != num_bones as usize {
+            bail!(
+                "num_bones = {num_bones:x}, tree_num_bones = {:x}",
+                skeleton.bones.len()
+            )
+        }
+
+        for (i, bone) in skeleton.bones.iter_mut().enumerate() {
+            file.seek(SeekFrom::Start(
+                header.offset_string_table + bone_material_offset[i],
+            ))?;
+            bone.name = file.read_u8str()?;
+        }
+        for mesh in &mut meshs {
+            file.seek(SeekFrom::Start(
+                header.offset_string_table + mesh.name_offset,
+            ))?;
+            mesh.name = file.read_u8str()?;
+        }
+        let mut material_names: Vec<String> = Vec::new();
+        for offset in material_name_offset {
+            file.seek(SeekFrom::Start(header.offset_string_table + offset))?;
+            material_names.push(file.read_u8str()?);
+        }
+
+        // The name table doesn't always have one entry per material (chr042), so materials are
+        // paired with their name by hash.
+        for material in &mut materials {
+            material.name = material_names
+                .iter()
+                .find(|name| name_hash(name) == material.name_hash)
+                .cloned();
+        }
+
+        Ok(Model {
+            header,
+            meshs,
+            materials,
+            material_names,
+            inv_matrixs,
+            skeleton,
+        })
+    }
+
+    /// Names meshes, bones and materials whose string-table entry is missing, where the
+    /// dictionary knows their hash.
+    pub fn resolve_names(&mut self, dict: &HashDict) {
+        let lookup = |hash: u32| dict.lookup(hash).and_then(|names| names.first()).cloned();
+        for mesh in &mut self.meshs {
+            if mesh.name.is_empty() {
+                mesh.name = lookup(mesh.name_hash).unwrap_or_default();
+            }
+        }
+        for bone in &mut self.skeleton.bones {
+            if bone.name.is_empty() {
+                bone.name = lookup(bone.name_hash).unwrap_or_default();
+            }
+        }
+        for material in &mut self.materials {
+            if material.name.is_none() {
+                material.name = lookup(material.name_hash);
+            }
+        }
+    }
+
+    /// Compares the world matrix of each bone, built from the tree, with the inverse of its
+    /// inverse bind matrix. Returns the bones whose elements differ by more than `tolerance`,
+    /// along with the largest difference.
+    pub fn bind_pose_mismatches(&self, tolerance: f32) -> Vec<(u16, f32)> {
+        let worlds = self.skeleton.world_matrices();
+        let mut mismatches: Vec<(u16, f32)> = Vec::new();
+        for (i, (world, inv_matrix)) in worlds.iter().zip(&self.inv_matrixs).enumerate() {
+            let error = match inv_matrix.try_inverse() {
+                Some(bind) => (world - bind).abs().max(),
+                None => f32::INFINITY,
+            };
+            if error > tolerance || error.is_nan() {
+                mismatches.push((i as u16, error));
+            }
+        }
+        mismatches
+    }
+
+    /// The names in the material name table that no material took, and the materials left
+    /// without a name.
+    pub fn unpaired_materials(&self) -> (Vec<&str>, Vec<usize>) {
+        let unused: Vec<&str> = self
+            .material_names
+            .iter()
+            .filter(|name| {
+                !self
+                    .materials
+                    .iter()
+                    .any(|m| m.name.as_ref() == Some(*name))
+            })
+            .map(String::as_str)
+            .collect();
+        let unnamed: Vec<usize> = (0..self.materials.len())
+            .filter(|&i| self.materials[i].name.is_none())
+            .collect();
+        (unused, unnamed)
+    }
+
+    /// The meshes whose `material_id` is past the end of the material table.
+    pub fn invalid_material_ids(&self) -> Vec<usize> {
+        (0..self.meshs.len())
+            .filter(|&i| self.material(&self.meshs[i]).is_none())
+            .collect()
+    }
+
+    /// Checks every name against its `name_hash`, describing the ones that don't match.
+    pub fn hash_mismatches(&self) -> Vec<String> {
+        fn check(kind: &str, i: usize, name: &str, hash: u32) -> Option<String> {
+            let expected = name_hash(name);
+            (expected != hash).then(|| {
+                format!("{kind}{i} {name:?}: name_hash = {hash:08X}, expected {expected:08X}")
+            })
+        }
+
+        let mut mismatches: Vec<String> = Vec::new();
+        for (i, mesh) in self.meshs.iter().enumerate() {
+            mismatches.extend(check("mesh", i, &mesh.name, mesh.name_hash));
+        }
+        for (i, bone) in self.skeleton.bones.iter().enumerate() {
+            mismatches.extend(check("bone", i, &bone.name, bone.name_hash));
+        }
+        for (i, material) in self.materials.iter().enumerate() {
+            if let Some(name) = &material.name {
+                mismatches.extend(check("material", i, name, material.name_hash));
+            }
+        }
+        mismatches
+    }
+}
+
+/// A quad stored as one strip and skinned to a two-bone skeleton, for the exporter tests.
+///
+/// Each vertex holds an f32 position, normal and texture coordinate, two u8 bone indices and two
+/// f32 weights.
+#[cfg(test)]
+pub fn test_model() -> Model {
+    let attr = |vtype, num, dtype, offset| Attr {
+        vtype,
+        normalize: 0,
+        num,
+        dtype,
+        flags: 0,
+        offset,
+    };
+    let vertexs: Vec<Vec<u8>> = [[0.0f32, 0.0], [1.0, 0.0], [0.0, 1.0], [1.0, 1.0]]
+        .iter()
+        .map(|&[x, y]| {
+            let mut vertex: Vec<u8> = Vec::new();
+            for value in [x, y, 0.0, 0.0, 0.0, 1.0, x, 1.0 - y] {
+                vertex.extend(value.to_le_bytes());
+            }
+            vertex.extend([0, 1, 0, 0]);
+            for value in [1.0 - y, y] {
+                vertex.extend(value.to_le_bytes());
+            }
+            vertex
+        })
+        .collect();
+    let bone = |name: &str, parent: u16, y: f32, children: Vec<u16>| Bone {
+        rotation: vec4(0.0, 0.0, 0.0, 1.0),
+        position: vec4(0.0, y, 0.0, 1.0),
+        scale: vec4(1.0, 1.0, 1.0, 1.0),
+        parent,
+        flags: 0,
+        children,
+        name_hash: name_hash(name),
+        name: name.to_owned(),
+    };
+    Model {
+        header: Header {
+            num_mesh: 1,
+            num_material: 1,
+            num_bones: 2,
+            ..Header::default()
+        },
+        meshs: vec![Mesh {
+            num_bone: 2,
+            num_attr: 5,
+            vertex_size: 44,
+            name_hash: name_hash("body"),
+            num_vertex: 4,
+            num_index: 4,
+            index_size: 2,
+            vertexs,
+            bone_map: vec![0, 1],
+            indexs: vec![0, 1, 2, 3],
+            attrs: vec![
+                attr(1, 3, 9, 0),
+                attr(2, 3, 9, 12),
+                attr(6, 2, 9, 24),
+                attr(10, 2, 0, 32),
+                attr(11, 2, 9, 36),
+            ],
+            name: "body".to_owned(),
+            ..Mesh::default()
+        }],
+        materials: vec![Material {
+            name_hash: name_hash("skin"),
+            name: Some("skin".to_owned()),
+        }],
+        material_names: vec!["skin".to_owned()],
+        inv_matrixs: vec![Mat4x4::identity(), translation(&vec3(0.0, -1.0, 0.0))],
+        skeleton: Skeleton {
+            bones: vec![
+                bone("root", 0x7FFF, 0.0, vec![1]),
+                bone("arm", 0, 1.0, vec![]),
+            ],
+            root_bones: vec![0],
+            ..Skeleton::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_winding_alternates() {
+        assert_eq!(
+            unfold_strip(&[0, 1, 2, 3, 4], 0xFFFF),
+            [0, 1, 2, 2, 1, 3, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn restart_resets_winding() {
+        assert_eq!(
+            unfold_strip(&[0, 1, 2, 3, 0xFFFF, 4, 5, 6, 7], 0xFFFF),
+            [0, 1, 2, 2, 1, 3, 4, 5, 6, 6, 5, 7]
+        );
+        assert_eq!(
+            unfold_strip(&[0, 1, 2, 0xFFFFFFFF, 3, 4, 5], 0xFFFFFFFF),
+            [0, 1, 2, 3, 4, 5]
+        );
+    }
+
+    #[test]
+    fn degenerate_triangles_keep_parity() {
+        // Two strips stitched by repeating 3 and 4.
+        assert_eq!(
+            unfold_strip(&[0, 1, 2, 3, 3, 4, 4, 5, 6], 0xFFFF),
+            [0, 1, 2, 2, 1, 3, 4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn index_width_from_buffer_size() {
+        assert_eq!(index_size(0, 0), Some(2));
+        assert_eq!(index_size(1, 4), Some(2));
+        assert_eq!(index_size(3, 8), Some(2));
+        assert_eq!(index_size(4, 8), Some(2));
+        assert_eq!(index_size(3, 12), Some(4));
+        assert_eq!(index_size(4, 16), Some(4));
+        assert_eq!(index_size(3, 10), None);
+    }
+
+    #[test]
+    fn reads_16_bit_indices() {
+        let bytes: Vec<u8> = [0u16, 1, 2, 0xFFFF, 3, 4, 5, 0]
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        assert_eq!(index_size(7, bytes.len() as u64), Some(2));
+        let indexs = read_indexs(&mut &bytes[..], 7, 2).unwrap();
+        assert_eq!(indexs, [0, 1, 2, 0xFFFF, 3, 4, 5]);
+        let mesh = Mesh {
+            index_size: 2,
+            indexs,
+            ..Mesh::default()
+        };
+        assert_eq!(mesh.strips(), [[0, 1, 2], [3, 4, 5]]);
+    }
+
+    #[test]
+    fn reads_32_bit_indices() {
+        let bytes: Vec<u8> = [0u32, 1, 2, 0xFFFFFFFF, 3, 0xFFFF, 0x10000]
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect();
+        assert_eq!(index_size(7, bytes.len() as u64), Some(4));
+        let indexs = read_indexs(&mut &bytes[..], 7, 4).unwrap();
+        assert_eq!(indexs, [0, 1, 2, 0xFFFFFFFF, 3, 0xFFFF, 0x10000]);
+        // 0xFFFF is an ordinary index at this width.
+        let mesh = Mesh {
+            index_size: 4,
+            indexs,
+            ..Mesh::default()
+        };
+        assert_eq!(mesh.strips(), [vec![0, 1, 2], vec![3, 0xFFFF, 0x10000]]);
+    }
+
+    #[test]
+    fn material_problems_are_reported() {
+        let mut model = test_model();
+        assert!(model.invalid_material_ids().is_empty());
+        assert_eq!(model.unpaired_materials(), (vec![], vec![]));
+
+        model.meshs[0].material_id = 1;
+        assert!(model.material(&model.meshs[0]).is_none());
+        assert_eq!(model.invalid_material_ids(), [0]);
+
+        model.material_names.push("cloth".to_owned());
+        model.materials.push(Material {
+            name_hash: name_hash("hair"),
+            name: None,
+        });
+        assert_eq!(model.unpaired_materials(), (vec!["cloth"], vec![1]));
+        assert!(model.hash_mismatches().is_empty());
+    }
+}
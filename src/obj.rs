@@ -0,0 +1,97 @@
+use crate::model::*;
+use anyhow::Result;
+use std::io::Write;
+
+/// OBJ and MTL statements take a single name, so whitespace in it is replaced.
+fn obj_name(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join("_")
+}
+
+fn material_name(model: &Model, index: usize) -> String {
+    match &model.materials[index].name {
+        Some(name) => obj_name(name),
+        None => format!("material{index}"),
+    }
+}
+
+/// Writes the meshes of a model in bind pose to `obj`, one `o`/`g` group per mesh, and their
+/// materials to `mtl`, which `obj` refers to as `mtl_name`.
+///
+/// Only positions, normals and the first texture coordinate set are kept. The model file doesn't
+/// reference textures, so materials only get a flat diffuse colour.
+pub fn export(
+    model: &Model,
+    mtl_name: &str,
+    obj: &mut impl Write,
+    mtl: &mut impl Write,
+) -> Result<()> {
+    writeln!(obj, "mtllib {mtl_name}")?;
+
+    let (mut num_positions, mut num_texcoords, mut num_normals) = (0, 0, 0);
+    for mesh in &model.meshs {
+        let name = obj_name(&mesh.name);
+        writeln!(obj, "o {name}")?;
+        writeln!(obj, "g {name}")?;
+        if model.material(mesh).is_some() {
+            writeln!(
+                obj,
+                "usemtl {}",
+                material_name(model, mesh.material_id as usize)
+            )?;
+        }
+
+        let Some(position) = mesh.attr(1) else {
+            continue;
+        };
+        let positions = mesh.decode(position, 3)?;
+        for v in positions.chunks_exact(3) {
+            writeln!(obj, "v {} {} {}", v[0], v[1], v[2])?;
+        }
+        let texcoords = match mesh.texcoords().first() {
+            Some(texcoord) => mesh.decode(texcoord, 2)?,
+            None => Vec::new(),
+        };
+        for vt in texcoords.chunks_exact(2) {
+            writeln!(obj, "vt {} {}", vt[0], vt[1])?;
+        }
+        let normals = match mesh.attr(2) {
+            Some(normal) => mesh.decode(normal, 3)?,
+            None => Vec::new(),
+        };
+        for vn in normals.chunks_exact(3) {
+            writeln!(obj, "vn {} {} {}", vn[0], vn[1], vn[2])?;
+        }
+
+        let vertex = |i: u32| -> String {
+            let v = num_positions + i + 1;
+            let vt = num_texcoords + i + 1;
+            let vn = num_normals + i + 1;
+            match (texcoords.is_empty(), normals.is_empty()) {
+                (true, true) => format!("{v}"),
+                (false, true) => format!("{v}/{vt}"),
+                (true, false) => format!("{v}//{vn}"),
+                (false, false) => format!("{v}/{vt}/{vn}"),
+            }
+        };
+        for triangle in mesh.triangles().chunks_exact(3) {
+            writeln!(
+                obj,
+                "f {} {} {}",
+                vertex(triangle[0]),
+                vertex(triangle[1]),
+                vertex(triangle[2])
+            )?;
+        }
+
+        num_positions += positions.len() as u32 / 3;
+        num_texcoords += texcoords.len() as u32 / 2;
+        num_normals += normals.len() as u32 / 3;
+    }
+
+    for i in 0..model.materials.len() {
+        writeln!(mtl, "newmtl {}", material_name(model, i))?;
+        writeln!(mtl, "Kd 0.8 0.8 0.8")?;
+        writeln!(mtl)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use crate::model::*;
+use anyhow::Result;
+use std::io::Write;
+
+/// The attributes of one mesh that make it into the PLY file. Empty when the mesh has none.
+#[derive(Default)]
+struct Vertices {
+    positions: Vec<f32>,
+    normals: Vec<f32>,
+    /// RGBA, alpha filled in when the mesh only has RGB.
+    colors: Vec<u8>,
+    texcoords: Vec<f32>,
+    /// Skeleton bone indices, already looked up in the bone map.
+    bones: Vec<u16>,
+    weights: Vec<f32>,
+    influences: usize,
+}
+
+impl Vertices {
+    fn read(mesh: &Mesh) -> Result<Vertices> {
+        let Some(position) = mesh.attr(1) else {
+            return Ok(Vertices::default());
+        };
+        let mut vertices = Vertices {
+            positions: mesh.decode(position, 3)?,
+            ..Vertices::default()
+        };
+        if let Some(normal) = mesh.attr(2) {
+            vertices.normals = mesh.decode(normal, 3)?;
+        }
+        if let Some(color) = mesh.attr(9) {
+            let num = color.num.min(4) as usize;
+            for c in mesh.decode(color, num as u16)?.chunks_exact(num) {
+                let mut rgba = [255u8; 4];
+                for (x, &value) in rgba.iter_mut().zip(c) {
+                    *x = (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+                vertices.colors.extend(rgba);
+            }
+        }
+        if let Some(texcoord) = mesh.texcoords().first() {
+            vertices.texcoords = mesh.decode(texcoord, 2)?;
+        }
+        if let (Some(bone), Some(weight)) = (mesh.attr(10), mesh.attr(11)) {
+            let num = bone.num.min(weight.num);
+            vertices.influences = num as usize;
+            vertices.bones = mesh
+                .decode(bone, num)?
+                .iter()
+                .map(|&b| match mesh.bone_map.get(b as usize) {
+                    Some(&bone) => bone as u16,
+                    None => b as u16,
+                })
+                .collect();
+            vertices.weights = mesh.decode(weight, num)?;
+        }
+        Ok(vertices)
+    }
+}
+
+/// Writes `meshs` as a single binary PLY file, one after the other in the vertex and face lists.
+///
+/// Besides positions, vertices carry normals, colours and the first texture coordinate set when
+/// any of the meshes has them, and skin influences as `bone<i>`/`weight<i>` properties. Bone
+/// indices are those of the skeleton. Meshes lacking an attribute get zeros, or white for colours.
+pub fn export(meshs: &[&Mesh], out: &mut impl Write) -> Result<()> {
+    let mut all: Vec<Vertices> = Vec::new();
+    let mut faces: Vec<u32> = Vec::new();
+    let mut num_vertices = 0;
+    for mesh in meshs {
+        let vertices = Vertices::read(mesh)?;
+        if vertices.positions.is_empty() {
+            continue;
+        }
+        faces.extend(mesh.triangles().iter().map(|i| num_vertices + i));
+        num_vertices += vertices.positions.len() as u32 / 3;
+        all.push(vertices);
+    }
+    let normals = all.iter().any(|v| !v.normals.is_empty());
+    let colors = all.iter().any(|v| !v.colors.is_empty());
+    let texcoords = all.iter().any(|v| !v.texcoords.is_empty());
+    let influences = all.iter().map(|v| v.influences).max().unwrap_or(0);
+
+    writeln!(out, "ply")?;
+    writeln!(out, "format binary_little_endian 1.0")?;
+    writeln!(out, "comment dstsmodel {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(out, "element vertex {num_vertices}")?;
+    for name in ["x", "y", "z"] {
+        writeln!(out, "property float {name}")?;
+    }
+    if normals {
+        for name in ["nx", "ny", "nz"] {
+            writeln!(out, "property float {name}")?;
+        }
+    }
+    if colors {
+        for name in ["red", "green", "blue", "alpha"] {
+            writeln!(out, "property uchar {name}")?;
+        }
+    }
+    if texcoords {
+        for name in ["s", "t"] {
+            writeln!(out, "property float {name}")?;
+        }
+    }
+    for i in 0..influences {
+        writeln!(out, "property ushort bone{i}")?;
+    }
+    for i in 0..influences {
+        writeln!(out, "property float weight{i}")?;
+    }
+    writeln!(out, "element face {}", faces.len() / 3)?;
+    writeln!(out, "property list uchar uint vertex_indices")?;
+    writeln!(out, "end_header")?;
+
+    for vertices in &all {
+        for i in 0..vertices.positions.len() / 3 {
+            let mut floats: Vec<f32> = vertices.positions[i * 3..][..3].to_vec();
+            if normals {
+                floats.extend(vertices.normals.get(i * 3..i * 3 + 3).unwrap_or(&[0.0; 3]));
+            }
+            for x in floats {
+                out.write_all(&x.to_le_bytes())?;
+            }
+            if colors {
+                out.write_all(vertices.colors.get(i * 4..i * 4 + 4).unwrap_or(&[255; 4]))?;
+            }
+            if texcoords {
+                let st = vertices
+                    .texcoords
+                    .get(i * 2..i * 2 + 2)
+                    .unwrap_or(&[0.0; 2]);
+                for x in st {
+                    out.write_all(&x.to_le_bytes())?;
+                }
+            }
+            let n = vertices.influences;
+            for j in 0..influences {
+                let bone = if j < n { vertices.bones[i * n + j] } else { 0 };
+                out.write_all(&bone.to_le_bytes())?;
+            }
+            for j in 0..influences {
+                let weight = if j < n {
+                    vertices.weights[i * n + j]
+                } else {
+                    0.0
+                };
+                out.write_all(&weight.to_le_bytes())?;
+            }
+        }
+    }
+    for face in faces.chunks_exact(3) {
+        out.write_all(&[3])?;
+        for i in face {
+            out.write_all(&i.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_and_counts() {
+        let model = test_model();
+        let mut ply: Vec<u8> = Vec::new();
+        export(&[&model.meshs[0]], &mut ply).unwrap();
+
+        let end = b"end_header\n";
+        let header_len = ply.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = std::str::from_utf8(&ply[..header_len]).unwrap();
+        let properties: Vec<&str> = header
+            .lines()
+            .filter_map(|line| line.strip_prefix("property float "))
+            .collect();
+        assert!(header.starts_with("ply\nformat binary_little_endian 1.0\n"));
+        assert!(header.contains("element vertex 4\n"));
+        assert!(header.contains("element face 2\n"));
+        assert!(header.contains("property ushort bone1\n"));
+        assert_eq!(
+            properties,
+            [
+                "x", "y", "z", "nx", "ny", "nz", "s", "t", "weight0", "weight1"
+            ]
+        );
+
+        // Per vertex: 8 floats, 2 ushort bones and 2 float weights. Per face: a count and 3 uints.
+        let body = &ply[header_len..];
+        assert_eq!(body.len(), 4 * (8 * 4 + 2 * 2 + 2 * 4) + 2 * (1 + 3 * 4));
+        let faces = &body[4 * 44..];
+        assert_eq!(faces[0], 3);
+        assert_eq!(faces[1..13], [0u32, 1, 2].map(u32::to_le_bytes).concat());
+    }
+}
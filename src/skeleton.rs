@@ -0,0 +1,201 @@
+use crate::file_ext::*;
+use crate::hash::*;
+use anyhow::{Result, bail};
+use nalgebra_glm::*;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Bone {
+    pub rotation: Vec4,
+    pub position: Vec4,
+    pub scale: Vec4,
+    pub parent: u16,
+    /// The bits stored above the parent index, meaning unknown.
+    pub flags: u16,
+    pub children: Vec<u16>,
+    pub name_hash: u32,
+    pub name: String,
+}
+
+impl Bone {
+    /// The rest pose transform relative to the parent bone.
+    pub fn local_matrix(&self) -> Mat4x4 {
+        let pos = translation(&self.position.xyz());
+        let rotation = quat_to_mat4(&Quat::from_vector(self.rotation));
+        let scale = scaling(&self.scale.xyz());
+        pos * rotation * scale
+    }
+}
+
+/// The bone tree, found at `offset_tree` of a model and in standalone skeleton files.
+///
+/// Offsets inside it are relative, so the same parser works wherever it starts. Bone names are
+/// not part of it; a model fills them in from its string table.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Skeleton {
+    pub magic: [u8; 4],
+    pub tree_bytes: u32,
+    pub tree_unk8: u32,
+    pub tree_footer_size: u32,
+    pub tree_unk12_g: u16,
+    pub tree_unk14: u32,
+    pub tree_unk_a: u32,
+    pub tree_unk_b: u32,
+    pub tree_unk_c: u32,
+
+    pub bones: Vec<Bone>,
+    pub root_bones: Vec<u16>,
+}
+
+impl Skeleton {
+    /// Reads a skeleton starting at the current position.
+    pub fn read(file: &mut (impl Read + Seek)) -> Result<Skeleton> {
+        let start = file.tell()?;
+        let file_size = file.seek(SeekFrom::End(0))?;
+        file.seek(SeekFrom::Start(start))?;
+
+        let magic = file.read_magic()?;
+        let tree_bytes = file.read_u32()?;
+        let tree_unk8 = file.read_u32()?;
+        let tree_footer_size = file.read_u32()?;
+        let num_bones = file.read_u16()?;
+        let tree_unk12_g = file.read_u16()?;
+        let tree_unk14 = file.read_u32()?;
+
+        if !magic.iter().all(u8::is_ascii_alphanumeric) {
+            bail!("Not a skeleton, magic = {magic:02X?}")
+        }
+        let end = start + tree_bytes as u64;
+        if end > file_size {
+            bail!("tree_bytes = {tree_bytes:x} is past the end of the file")
+        }
+        if tree_footer_size > tree_bytes {
+            bail!("tree_footer_size = {tree_footer_size:x}, tree_bytes = {tree_bytes:x}")
+        }
+
+        let offset_bone = file.tell()? + file.read_u32()? as u64;
+        let offset_parents = file.tell()? + file.read_u32()? as u64;
+        let offset_bone_name_hash = file.tell()? + file.read_u32()? as u64;
+        let offset_e = file.tell()? + file.read_u32()? as u64;
+        let offset_f = file.tell()? + file.read_u32()? as u64;
+        let offset_g = file.tell()? + file.read_u32()? as u64;
+        let tree_unk_a = file.read_u32()?;
+        let tree_unk_b = file.read_u32()?;
+        let tree_unk_c = file.read_u32()?;
+        let num_rel = file.read_u32()?;
+
+        for offset in [
+            offset_bone,
+            offset_parents,
+            offset_bone_name_hash,
+            offset_e,
+            offset_f,
+            offset_g,
+        ] {
+            if offset > end {
+                bail!("Offset 0x{offset:08X} is past the end of the skeleton at 0x{end:08X}")
+            }
+        }
+
+        let mut rels: Vec<(u16, u16)> = Vec::new();
+        for _ in 0..num_rel {
+            let child = file.read_u16()?;
+            let parent = file.read_u16()?;
+            rels.push((child, parent))
+        }
+
+        let mut bones: Vec<Bone> = Vec::new();
+        file.seek_noop(offset_bone)?;
+        for _ in 0..num_bones {
+            let rotation = file.read_f32vec4()?;
+            let position = file.read_f32vec4()?;
+            let scale = file.read_f32vec4()?;
+            bones.push(Bone {
+                rotation,
+                position,
+                scale,
+                ..Bone::default()
+            })
+        }
+
+        let mut root_bones: Vec<u16> = Vec::new();
+        file.seek_noop(offset_parents)?;
+        for i in 0..num_bones {
+            let index = file.read_u16()? as usize;
+            if index >= rels.len() || rels[index].0 != i {
+                bail!("Bone child {i}")
+            }
+            let parent = rels[index].1 & 0x7FFF; // What is the high bit? see chr359
+            if parent != 0x7FFF && parent >= num_bones {
+                bail!("Bone parent {i}")
+            }
+            bones[i as usize].parent = parent;
+            bones[i as usize].flags = rels[index].1 & 0x8000;
+            if parent != 0x7FFF {
+                bones[parent as usize].children.push(i);
+            } else {
+                root_bones.push(i)
+            }
+        }
+
+        file.seek_assert_align_up(offset_g, 4)?;
+
+        // something in between..
+        file.seek(SeekFrom::Start(offset_bone_name_hash))?; // should align to 16
+        for bone in &mut bones {
+            bone.name_hash = file.read_u32()?;
+        }
+
+        file.seek_noop(offset_e)?;
+
+        Ok(Skeleton {
+            magic,
+            tree_bytes,
+            tree_unk8,
+            tree_footer_size,
+            tree_unk12_g,
+            tree_unk14,
+            tree_unk_a,
+            tree_unk_b,
+            tree_unk_c,
+            bones,
+            root_bones,
+        })
+    }
+
+    /// The rest pose transform of every bone relative to the skeleton root.
+    pub fn world_matrices(&self) -> Vec<Mat4x4> {
+        fn visit(bones: &[Bone], index: u16, parent: &Mat4x4, worlds: &mut [Mat4x4]) {
+            let bone = &bones[index as usize];
+            let world = parent * bone.local_matrix();
+            worlds[index as usize] = world;
+            for &child in &bone.children {
+                visit(bones, child, &world, worlds);
+            }
+        }
+
+        let mut worlds = vec![Mat4x4::identity(); self.bones.len()];
+        for &root in &self.root_bones {
+            visit(&self.bones, root, &Mat4x4::identity(), &mut worlds);
+        }
+        worlds
+    }
+
+    /// Names bones that have no name yet, using the dictionary where it knows the hash and the
+    /// hash itself otherwise.
+    pub fn resolve_names(&mut self, dict: Option<&HashDict>) {
+        for bone in &mut self.bones {
+            if !bone.name.is_empty() {
+                continue;
+            }
+            let known = dict
+                .and_then(|dict| dict.lookup(bone.name_hash))
+                .and_then(|names| names.first());
+            bone.name = match known {
+                Some(name) => name.clone(),
+                None => format!("{:08X}", bone.name_hash),
+            };
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::model::*;
+use anyhow::Result;
+use nalgebra_glm::*;
+use std::io::Write;
+
+/// Writes the triangles of `meshs` as binary STL. STL has no notion of parts, so they all end up
+/// in one solid.
+///
+/// Facet normals are computed from the triangles, since STL has no per-vertex normals.
+pub fn export(meshs: &[&Mesh], out: &mut impl Write) -> Result<()> {
+    let mut triangles: Vec<[Vec3; 3]> = Vec::new();
+    for mesh in meshs {
+        let Some(position) = mesh.attr(1) else {
+            continue;
+        };
+        let positions = mesh.decode(position, 3)?;
+        let vertex = |i: u32| make_vec3(&positions[i as usize * 3..][..3]);
+        for triangle in mesh.triangles().chunks_exact(3) {
+            triangles.push([
+                vertex(triangle[0]),
+                vertex(triangle[1]),
+                vertex(triangle[2]),
+            ]);
+        }
+    }
+
+    let mut header = [0u8; 80];
+    header[..9].copy_from_slice(b"dstsmodel");
+    out.write_all(&header)?;
+    out.write_all(&(triangles.len() as u32).to_le_bytes())?;
+    for [a, b, c] in triangles {
+        let cross = (b - a).cross(&(c - a));
+        let normal = if cross.norm() > 0.0 {
+            cross.normalize()
+        } else {
+            Vec3::zeros()
+        };
+        for v in [normal, a, b, c] {
+            for x in v.iter() {
+                out.write_all(&x.to_le_bytes())?;
+            }
+        }
+        out.write_all(&0u16.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_header_and_triangles() {
+        let model = test_model();
+        let mut stl: Vec<u8> = Vec::new();
+        export(&[&model.meshs[0]], &mut stl).unwrap();
+
+        assert_eq!(stl.len(), 80 + 4 + 2 * 50);
+        assert!(stl[..80].starts_with(b"dstsmodel"));
+        assert_eq!(stl[80..84], 2u32.to_le_bytes());
+        let floats: Vec<f32> = stl[84..][..48]
+            .chunks_exact(4)
+            .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats, [0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0.]);
+    }
+}
@@ -0,0 +1,310 @@
+use crate::dae::LengthUnit;
+use crate::model::*;
+use crate::skeleton::*;
+use anyhow::{Result, bail};
+use nalgebra_glm::*;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::io::Write;
+
+/// Turns `name` into a prim or joint name, falling back to `fallback` when nothing is left of it
+/// and numbering it with `index`, then a counter, until `used` doesn't have it yet.
+fn identifier(name: &str, fallback: &str, index: usize, used: &mut HashSet<String>) -> String {
+    let mut result: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if result.is_empty() {
+        result = fallback.to_owned();
+    }
+    if result.starts_with(|c: char| c.is_ascii_digit()) {
+        result.insert(0, '_');
+    }
+    let base = result.clone();
+    let mut n = 0;
+    while !used.insert(result.clone()) {
+        result = match n {
+            0 => format!("{base}_{index}"),
+            _ => format!("{base}_{index}_{n}"),
+        };
+        n += 1;
+    }
+    result
+}
+
+fn list<T: Display>(values: impl IntoIterator<Item = T>) -> String {
+    let values: Vec<String> = values.into_iter().map(|v| v.to_string()).collect();
+    values.join(", ")
+}
+
+fn array<T: Display>(values: impl IntoIterator<Item = T>) -> String {
+    format!("[{}]", list(values))
+}
+
+fn tuples(values: &[f32], num: usize) -> String {
+    array(values.chunks_exact(num).map(|v| format!("({})", list(v))))
+}
+
+/// USD transforms act on row vectors, so each column of `m` is written as a row.
+fn matrix(m: &Mat4x4) -> String {
+    let rows: Vec<String> = (0..4)
+        .map(|j| {
+            format!(
+                "({}, {}, {}, {})",
+                m[(0, j)],
+                m[(1, j)],
+                m[(2, j)],
+                m[(3, j)]
+            )
+        })
+        .collect();
+    format!("( {} )", rows.join(", "))
+}
+
+/// The paths of the joints, and the bones in joint order: every parent before its children, as
+/// UsdSkel requires. Fails on bones that can't be reached from the root bones, as they'd have no
+/// path.
+fn joints(skeleton: &Skeleton) -> Result<(Vec<String>, Vec<u16>)> {
+    fn visit(
+        bones: &[Bone],
+        index: u16,
+        parent: Option<&str>,
+        used: &mut HashSet<String>,
+        paths: &mut [String],
+        order: &mut Vec<u16>,
+    ) {
+        let bone = &bones[index as usize];
+        let name = identifier(&bone.name, &format!("bone{index}"), index as usize, used);
+        let path = match parent {
+            Some(parent) => format!("{parent}/{name}"),
+            None => name,
+        };
+        order.push(index);
+        for &child in &bone.children {
+            visit(bones, child, Some(&path), used, paths, order);
+        }
+        paths[index as usize] = path;
+    }
+
+    let mut used: HashSet<String> = HashSet::new();
+    let mut paths = vec![String::new(); skeleton.bones.len()];
+    let mut order: Vec<u16> = Vec::new();
+    for &root in &skeleton.root_bones {
+        visit(
+            &skeleton.bones,
+            root,
+            None,
+            &mut used,
+            &mut paths,
+            &mut order,
+        );
+    }
+    if let Some(i) = paths.iter().position(String::is_empty) {
+        bail!("bone{i} can't be reached from the root bones")
+    }
+    Ok((paths, order))
+}
+
+/// Writes a model as an ASCII USD layer: a `SkelRoot` holding a `Skeleton` built from the bone
+/// tree and one `Mesh` per mesh, bound to the skeleton through its bone map.
+///
+/// Bind transforms are the inverses of the inverse bind matrices, falling back to the tree for
+/// bones whose matrix can't be inverted. Mesh positions are already in model space, so they need
+/// no geometry bind transform. Meshes that only follow one bone are bound to it rigidly.
+///
+/// The game doesn't record its unit of length, so `metersPerUnit` is only written when
+/// `source_unit` is given.
+pub fn export(model: &Model, source_unit: Option<LengthUnit>, out: &mut impl Write) -> Result<()> {
+    let skeleton = &model.skeleton;
+    let skinned = !skeleton.bones.is_empty();
+
+    writeln!(out, "#usda 1.0")?;
+    writeln!(out, "(")?;
+    writeln!(out, "    defaultPrim = \"Model\"")?;
+    writeln!(out, "    doc = \"dstsmodel {}\"", env!("CARGO_PKG_VERSION"))?;
+    if let Some(unit) = source_unit {
+        writeln!(out, "    metersPerUnit = {}", unit.meters())?;
+    }
+    writeln!(out, "    upAxis = \"Y\"")?;
+    writeln!(out, ")")?;
+    writeln!(out)?;
+    let kind = if skinned { "SkelRoot" } else { "Xform" };
+    writeln!(out, "def {kind} \"Model\"")?;
+    writeln!(out, "{{")?;
+
+    let (paths, order) = joints(skeleton)?;
+    if skinned {
+        let worlds = skeleton.world_matrices();
+        let binds: Vec<Mat4x4> = order
+            .iter()
+            .map(|&i| {
+                let inv = model.inv_matrixs.get(i as usize);
+                match inv.and_then(|m| m.try_inverse()) {
+                    Some(bind) => bind,
+                    None => worlds[i as usize],
+                }
+            })
+            .collect();
+        let rests: Vec<Mat4x4> = order
+            .iter()
+            .map(|&i| skeleton.bones[i as usize].local_matrix())
+            .collect();
+
+        writeln!(out, "    def Skeleton \"Skeleton\"")?;
+        writeln!(out, "    {{")?;
+        let joints = order.iter().map(|&i| format!("\"{}\"", paths[i as usize]));
+        writeln!(out, "        uniform token[] joints = {}", array(joints))?;
+        let binds = binds.iter().map(matrix);
+        writeln!(
+            out,
+            "        uniform matrix4d[] bindTransforms = {}",
+            array(binds)
+        )?;
+        let rests = rests.iter().map(matrix);
+        writeln!(
+            out,
+            "        uniform matrix4d[] restTransforms = {}",
+            array(rests)
+        )?;
+        writeln!(out, "    }}")?;
+    }
+
+    let mut used: HashSet<String> = ["Skeleton".to_owned()].into();
+    for (i, mesh) in model.meshs.iter().enumerate() {
+        let Some(position) = mesh.attr(1) else {
+            continue;
+        };
+        let name = identifier(&mesh.name, &format!("mesh{i}"), i, &mut used);
+        let bones = mesh.attr(10);
+        let weights = mesh.attr(11);
+        let bound = skinned && !mesh.bone_map.is_empty();
+
+        writeln!(out)?;
+        if bound {
+            writeln!(out, "    def Mesh \"{name}\" (")?;
+            writeln!(out, "        prepend apiSchemas = [\"SkelBindingAPI\"]")?;
+            writeln!(out, "    )")?;
+        } else {
+            writeln!(out, "    def Mesh \"{name}\"")?;
+        }
+        writeln!(out, "    {{")?;
+
+        let triangles = mesh.triangles();
+        let counts = vec![3; triangles.len() / 3];
+        writeln!(out, "        int[] faceVertexCounts = {}", array(counts))?;
+        writeln!(
+            out,
+            "        int[] faceVertexIndices = {}",
+            array(triangles)
+        )?;
+        let points = mesh.decode(position, 3)?;
+        writeln!(out, "        point3f[] points = {}", tuples(&points, 3))?;
+        if let Some(normal) = mesh.attr(2) {
+            let normals = tuples(&mesh.decode(normal, 3)?, 3);
+            writeln!(out, "        normal3f[] normals = {normals} (")?;
+            writeln!(out, "            interpolation = \"vertex\"")?;
+            writeln!(out, "        )")?;
+        }
+        if let Some(texcoord) = mesh.texcoords().first() {
+            let st = tuples(&mesh.decode(texcoord, 2)?, 2);
+            writeln!(out, "        texCoord2f[] primvars:st = {st} (")?;
+            writeln!(out, "            interpolation = \"vertex\"")?;
+            writeln!(out, "        )")?;
+        }
+        if let Some(color) = mesh.attr(9).filter(|a| a.num >= 3) {
+            let colors = tuples(&mesh.decode(color, 3)?, 3);
+            writeln!(out, "        color3f[] primvars:displayColor = {colors} (")?;
+            writeln!(out, "            interpolation = \"vertex\"")?;
+            writeln!(out, "        )")?;
+        }
+
+        if bound {
+            // Like the COLLADA export, a mesh without weights follows the first bone of its
+            // palette.
+            let (indices, weights, size, interpolation) = match (bones, weights) {
+                (Some(bones), Some(weights)) if mesh.bone_map.len() > 1 => {
+                    let num = bones.num.min(weights.num);
+                    let indices = mesh.decode(bones, num)?.iter().map(|&b| b as u32).collect();
+                    (indices, mesh.decode(weights, num)?, num, "vertex")
+                }
+                _ => (vec![0], vec![1.0], 1, "constant"),
+            };
+            writeln!(
+                out,
+                "        int[] primvars:skel:jointIndices = {} (",
+                array(indices)
+            )?;
+            writeln!(out, "            elementSize = {size}")?;
+            writeln!(out, "            interpolation = \"{interpolation}\"")?;
+            writeln!(out, "        )")?;
+            writeln!(
+                out,
+                "        float[] primvars:skel:jointWeights = {} (",
+                array(weights)
+            )?;
+            writeln!(out, "            elementSize = {size}")?;
+            writeln!(out, "            interpolation = \"{interpolation}\"")?;
+            writeln!(out, "        )")?;
+            let mut palette: Vec<String> = Vec::new();
+            for &bone in &mesh.bone_map {
+                let Some(path) = paths.get(bone as usize) else {
+                    bail!("Mesh {i} uses bone {bone}, skeleton has {}", paths.len())
+                };
+                palette.push(format!("\"{path}\""));
+            }
+            writeln!(
+                out,
+                "        uniform token[] skel:joints = {}",
+                array(palette)
+            )?;
+            writeln!(out, "        rel skel:skeleton = </Model/Skeleton>")?;
+        }
+        writeln!(out, "        uniform token subdivisionScheme = \"none\"")?;
+        writeln!(out, "    }}")?;
+    }
+
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_skinned_mesh() {
+        let mut usda: Vec<u8> = Vec::new();
+        export(&test_model(), Some(LengthUnit::Centimetre), &mut usda).unwrap();
+        let usda = String::from_utf8(usda).unwrap();
+
+        assert!(usda.starts_with("#usda 1.0\n"));
+        for expected in [
+            "    metersPerUnit = 0.01\n",
+            "def SkelRoot \"Model\"",
+            "uniform token[] joints = [\"root\", \"root/arm\"]",
+            "(0, 0, 0, 1) ), ( (1, 0, 0, 0), (0, 1, 0, 0), (0, 0, 1, 0), (0, 1, 0, 1) )]",
+            "def Mesh \"body\"",
+            "int[] faceVertexIndices = [0, 1, 2, 2, 1, 3]",
+            "point3f[] points = [(0, 0, 0), (1, 0, 0), (0, 1, 0), (1, 1, 0)]",
+            "int[] primvars:skel:jointIndices = [0, 1, 0, 1, 0, 1, 0, 1]",
+            "rel skel:skeleton = </Model/Skeleton>",
+        ] {
+            assert!(usda.contains(expected), "{expected} missing from\n{usda}");
+        }
+    }
+
+    #[test]
+    fn identifiers_stay_unique() {
+        let mut used: HashSet<String> = ["arm", "arm_1"].map(|s| s.to_owned()).into();
+        assert_eq!(identifier("arm", "bone1", 1, &mut used), "arm_1_1");
+        assert_eq!(identifier("arm", "bone1", 1, &mut used), "arm_1_2");
+        assert_eq!(identifier("", "bone2", 2, &mut used), "bone2");
+    }
+
+    #[test]
+    fn unreachable_bones_are_rejected() {
+        let mut model = test_model();
+        model.skeleton.bones[0].children.clear();
+        assert!(export(&model, None, &mut Vec::new()).is_err());
+    }
+}